@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::process::ExitCode;
+use object_system_prototyping::export::{export_object, export_objects};
+use object_system_prototyping::import::{import_path, Imported, SymlinkPolicy};
+use object_system_prototyping::library::Library;
+use object_system_prototyping::object::{Form, MergePolicy, ObjectId, Tag};
+use object_system_prototyping::query::{form_name, parse_tag, Query};
+use object_system_prototyping::similar::{near_duplicates, perceptual_hash, THRESHOLD};
+
+const USAGE: &str = "Usage: objsys [--store <path>] <command>
+
+Commands:
+    import [--symlinks skip|files|follow] [--merge union|existing|incoming] <paths...>
+                                Import files, and directories recursively. Files that are
+                                already stored are reported, and their tags merged as --merge
+                                says, union by default
+    list                        List all objects
+    search <query>              List objects matching a query
+    show <id>                   Show the form and tags of an object
+    tag add <id> <kind:value>   Add a tag, e.g. title:Holiday, date.created:2020-01-15 or
+                                location:48.8584,2.2945,Eiffel Tower
+    tag set <id> <kind:value>   Add a tag, replacing the title or date it conflicts with
+    tag remove <id> <kind:value>
+    export <id> <path>          Write the data of an object to a file, and its tags to <path>.json
+    export --to <dir> <query>   Write every object matching a query into a directory
+    delete <ids...>             Delete objects
+    merge <id> <ids...>         Merge the tags of duplicates into an object, then delete them
+    similar [<threshold>]       List groups of photos that look alike, from 0 (the same) to 64
+                                differing bits of their perceptual hashes, 10 by default
+
+Ids may be abbreviated to any unique prefix.";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let mut store = env::var("OBJSYS_STORE").unwrap_or("object_store".into());
+    if args.first().map(String::as_str) == Some("--store") {
+        if args.len() < 2 {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+        store = args.remove(1);
+        args.remove(0);
+    }
+    match run(&store, args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("objsys: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(store: &str, args: Vec<String>) -> Result<(), String> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let mut library = Library::load(store).map_err(|e| format!("could not load {store}: {e}"))?;
+    if let Some(backup) = library.recovered_from() {
+        eprintln!("objsys: {store} was damaged, restored from {}", backup.display());
+    }
+    match args.as_slice() {
+        ["import", args @ ..] => {
+            let (symlinks, merge, paths) = import_options(args)?;
+            import(&mut library, paths, symlinks, merge);
+            library.save().map_err(|e| format!("could not save {store}: {e}"))?;
+        }
+        ["list"] => list(&library, &Query::All),
+        ["search", query @ ..] => list(&library, &Query::parse(query.join(" ").as_str())?),
+        ["show", id] => {
+            let object = library.get(&find(&library, id)?).unwrap();
+            println!("Id    {}\nForm  {}\nSize  {} bytes", object.id, object.describe(), object.data.size());
+            for tag in &object.tags {
+                println!("{tag}");
+            }
+        }
+        ["tag", action @ ("add" | "set" | "remove"), id, tag] => {
+            let id = find(&library, id)?;
+            let tag = parse_kind_value(tag)?;
+            let mut result = Ok(());
+            library.update(&id, |object| {
+                result = match (*action, object.conflicting_tag(&tag).cloned()) {
+                    ("set", Some(old)) => object.replace_tag(&old, tag),
+                    ("add" | "set", _) => object.add_tag(tag),
+                    _ if object.remove_tag(&tag) => Ok(()),
+                    _ => Err(format!("not tagged {tag}")),
+                };
+            });
+            result?;
+            library.save().map_err(|e| format!("could not save {store}: {e}"))?;
+        }
+        ["export", "--to", dir, query @ ..] => {
+            let query = Query::parse(query.join(" ").as_str())?;
+            let objects = library.search(&query).iter().filter_map(|id| library.get(id)).collect::<Vec<_>>();
+            for path in export_objects(objects, Path::new(dir)).map_err(|e| format!("could not export: {e}"))? {
+                println!("{}", path.display());
+            }
+        }
+        ["export", id, path] => {
+            let object = library.get(&find(&library, id)?).unwrap();
+            export_object(object, Path::new(path)).map_err(|e| format!("could not export: {e}"))?;
+        }
+        ["delete", ids @ ..] if !ids.is_empty() => {
+            for id in ids {
+                let id = find(&library, id)?;
+                library.remove(&id);
+            }
+            library.save().map_err(|e| format!("could not save {store}: {e}"))?;
+        }
+        ["merge", id, duplicates @ ..] if !duplicates.is_empty() => {
+            let id = find(&library, id)?;
+            for duplicate in duplicates {
+                let duplicate = find(&library, duplicate)?;
+                if !library.merge_into(&id, &duplicate, MergePolicy::Union) {
+                    return Err(format!("cannot merge {duplicate} into itself"));
+                }
+            }
+            library.save().map_err(|e| format!("could not save {store}: {e}"))?;
+        }
+        ["similar", threshold @ ..] if threshold.len() <= 1 => {
+            let threshold = match threshold.first() {
+                Some(threshold) => threshold.parse().map_err(|_| format!("{threshold} is not a number of bits"))?,
+                None => THRESHOLD,
+            };
+            similar(&library, threshold);
+        }
+        _ => return Err(format!("unknown command\n\n{USAGE}")),
+    }
+    Ok(())
+}
+
+// The options of the import command, which come before the paths.
+fn import_options<'a>(mut args: &'a [&'a str]) -> Result<(SymlinkPolicy, MergePolicy, &'a [&'a str]), String> {
+    let (mut symlinks, mut merge) = (SymlinkPolicy::Skip, MergePolicy::Union);
+    loop {
+        match args {
+            ["--symlinks", policy, rest @ ..] => {
+                symlinks = match *policy {
+                    "skip" => SymlinkPolicy::Skip,
+                    "files" => SymlinkPolicy::FollowFiles,
+                    "follow" => SymlinkPolicy::Follow,
+                    _ => return Err(format!("unknown symlink policy {policy}")),
+                };
+                args = rest;
+            }
+            ["--merge", policy, rest @ ..] => {
+                merge = match *policy {
+                    "union" => MergePolicy::Union,
+                    "existing" => MergePolicy::KeepExisting,
+                    "incoming" => MergePolicy::KeepIncoming,
+                    _ => return Err(format!("unknown merge policy {policy}")),
+                };
+                args = rest;
+            }
+            [] => return Err(format!("nothing to import\n\n{USAGE}")),
+            _ => return Ok((symlinks, merge, args)),
+        }
+    }
+}
+
+fn import(library: &mut Library, paths: &[&str], symlinks: SymlinkPolicy, merge: MergePolicy) {
+    for path in paths {
+        for (path, imported) in import_path(Path::new(path), library, merge, symlinks) {
+            match imported {
+                Ok(Imported::New(id)) => println!("{id}  {}", path.display()),
+                Ok(Imported::Duplicate {id, kept, dropped}) => {
+                    println!("{id}  {}  (already stored)", path.display());
+                    for tag in kept {
+                        println!("    + {tag}");
+                    }
+                    for tag in dropped {
+                        println!("    - {tag}");
+                    }
+                }
+                Err(e) => eprintln!("objsys: could not import {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+fn similar(library: &Library, threshold: u32) {
+    let mut hashes = HashMap::new();
+    for object in library.objects().filter(|object| object.form == Form::Photo) {
+        match perceptual_hash(library.root(), object) {
+            Ok(hash) => {
+                hashes.insert(object.id, hash);
+            }
+            Err(e) => eprintln!("objsys: could not compare {}: {e}", object.id),
+        }
+    }
+    for group in near_duplicates(&hashes, threshold) {
+        for id in group {
+            println!("{id}  {:016x}", hashes[&id]);
+        }
+        println!();
+    }
+}
+
+fn list(library: &Library, query: &Query) {
+    let mut objects = library.search(query).iter().filter_map(|id| library.get(id)).collect::<Vec<_>>();
+    objects.sort_by_key(|object| object.id.to_string());
+    for object in objects {
+        let title = object.tags.iter().find_map(|tag| match tag {
+            Tag::Title(title) => Some(title.as_str()),
+            _ => None,
+        });
+        println!("{}  {:8} {:>10}  {}", object.id, form_name(&object.form), object.data.size(), title.unwrap_or(""));
+    }
+}
+
+// Finds the object whose id starts with `prefix`.
+fn find(library: &Library, prefix: &str) -> Result<ObjectId, String> {
+    let prefix = prefix.to_lowercase();
+    let ids = library.objects().map(|object| object.id).filter(|id| id.to_string().starts_with(&prefix)).collect::<Vec<_>>();
+    match ids.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(format!("no object with id {prefix}")),
+        _ => Err(format!("id {prefix} is ambiguous")),
+    }
+}
+
+// Tags are written kind:value, e.g. title:Holiday.
+fn parse_kind_value(tag: &str) -> Result<Tag, String> {
+    let (kind, value) = tag.split_once(':').ok_or(format!("tag {tag} is not of the form kind:value"))?;
+    parse_tag(kind, value)
+}
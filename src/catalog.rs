@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::schema::{self, SCHEMA};
+use crate::store::Entry;
+
+// The catalog of a store as it is written to disk:
+//   magic    8 bytes, "OBJCATLG"
+//   version  u16
+//   schema   u16, how the entries are serialized, see schema.rs. Version 1 catalogs lack it and
+//            are schema 1.
+//   count    u32, the number of records that follow
+//   records  a u32 length and the CRC-32 of the record as a u32, then the record itself: the
+//            32-byte object id followed by its entry in CBOR.
+// Integers are little-endian. A record that fails its checksum fails the whole catalog, so the
+// store falls back to a backup rather than silently losing the object.
+pub const MAGIC: &[u8; 8] = b"OBJCATLG";
+pub const VERSION: u16 = 2;
+
+pub fn encode(path: &Path, catalog: &HashMap<ObjectId, Entry>) -> Result<Vec<u8>> {
+    let mut data = MAGIC.to_vec();
+    data.extend(VERSION.to_le_bytes());
+    data.extend(SCHEMA.to_le_bytes());
+    data.extend((catalog.len() as u32).to_le_bytes());
+    // Sorted so that an unchanged catalog is written the same.
+    let mut ids = catalog.keys().collect::<Vec<_>>();
+    ids.sort_by_key(|id| id.as_bytes());
+    for id in ids {
+        let mut record = id.as_bytes().to_vec();
+        ciborium::ser::into_writer(&catalog[id], &mut record).map_err(Error::parse(path))?;
+        data.extend((record.len() as u32).to_le_bytes());
+        data.extend(crc32fast::hash(record.as_slice()).to_le_bytes());
+        data.extend(record);
+    }
+    Ok(data)
+}
+
+// Entries written in an older schema are migrated. A catalog of a newer version or schema is
+// rejected with Error::Version rather than read wrongly.
+pub fn decode(path: &Path, data: &[u8]) -> Result<HashMap<ObjectId, Entry>> {
+    let mut rest = data.strip_prefix(MAGIC.as_slice()).ok_or("not a catalog").map_err(Error::parse(path))?;
+    let mut take = |n: usize| {
+        let taken = rest.get(..n).ok_or("the catalog is cut short").map_err(Error::parse(path))?;
+        rest = &rest[n..];
+        Ok(taken)
+    };
+    let version = u16::from_le_bytes(take(2)?.try_into().unwrap());
+    if version > VERSION {
+        return Err(Error::Version {path: path.to_path_buf(), version});
+    }
+    let schema = if version >= 2 { u16::from_le_bytes(take(2)?.try_into().unwrap()) } else { 1 };
+    if schema > SCHEMA {
+        return Err(Error::Version {path: path.to_path_buf(), version: schema});
+    }
+    if schema == 0 {
+        return Err(Error::parse(path)("schema 0 doesn't exist"));
+    }
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut catalog = HashMap::new();
+    for n in 0..count {
+        let length = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let record = take(length)?;
+        if length < 32 || crc32fast::hash(record) != checksum {
+            return Err(Error::parse(path)(format!("record {n} is damaged")));
+        }
+        let entry = ciborium::de::from_reader(&record[32..]).map_err(|e| e.to_string())
+            .and_then(|entry| schema::entry(entry, schema))
+            .map_err(|e| Error::parse(path)(format!("record {n} is damaged: {e}")))?;
+        catalog.insert(ObjectId::from_bytes(record[..32].try_into().unwrap()), entry);
+    }
+    if !rest.is_empty() {
+        return Err(Error::parse(path)("the catalog has trailing data"));
+    }
+    Ok(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use serde_json::json;
+    use crate::object::{Form, Tag};
+    use super::*;
+
+    fn catalog() -> HashMap<ObjectId, Entry> {
+        let tags = HashSet::from([Tag::Title("Moon".into()), Tag::Exif {tag: "Model".into(), value: "D50".into(), ifd: 1}]);
+        HashMap::from([
+            (ObjectId::of(b"moon"), Entry {tags, form: Form::Photo, words: None}),
+            (ObjectId::of(b""), Entry {tags: HashSet::new(), form: Form::OtherUnknown("Hologram".into()), words: Some(["hologram".into(), "of".into()].into())}),
+        ])
+    }
+
+    fn same(a: &HashMap<ObjectId, Entry>, b: &HashMap<ObjectId, Entry>) -> bool {
+        a.len() == b.len() && a.iter().all(|(id, entry)| b.get(id).is_some_and(|other| other.tags == entry.tags && other.form == entry.form && other.words == entry.words))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = Path::new("catalog.bin");
+        let data = encode(path, &catalog()).unwrap();
+        assert!(same(&decode(path, &data).unwrap(), &catalog()));
+        // Written the same however the map happens to be ordered.
+        assert!(encode(path, &decode(path, &data).unwrap()).unwrap() == data);
+        assert!(decode(path, &encode(path, &HashMap::new()).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn damage_is_detected() {
+        let path = Path::new("catalog.bin");
+        let data = encode(path, &catalog()).unwrap();
+        // Every byte of every record is covered by its checksum.
+        for i in 16..data.len() {
+            let mut damaged = data.clone();
+            damaged[i] ^= 0x40;
+            assert!(decode(path, &damaged).is_err(), "byte {i}");
+        }
+        assert!(decode(path, &data[..data.len() - 1]).is_err());
+        assert!(decode(path, &[data.as_slice(), &[0]].concat()).is_err());
+        assert!(decode(path, b"OBJCATL").is_err());
+        assert!(decode(path, &[b"NOTCATLG", &data[8..]].concat()).is_err());
+    }
+
+    #[test]
+    fn newer_catalogs_are_rejected() {
+        let path = Path::new("catalog.bin");
+        let data = encode(path, &catalog()).unwrap();
+        let newer_version = [&data[..8], &(VERSION + 1).to_le_bytes(), &data[10..]].concat();
+        assert!(matches!(decode(path, &newer_version), Err(Error::Version {version, ..}) if version == VERSION + 1));
+        let newer_schema = [&data[..10], &(SCHEMA + 1).to_le_bytes(), &data[12..]].concat();
+        assert!(matches!(decode(path, &newer_schema), Err(Error::Version {version, ..}) if version == SCHEMA + 1));
+        let no_schema = [&data[..10], &0u16.to_le_bytes(), &data[12..]].concat();
+        assert!(matches!(decode(path, &no_schema), Err(Error::Parse {..})));
+    }
+
+    #[test]
+    fn version_1_is_migrated() {
+        let path = Path::new("catalog.bin");
+        let id = ObjectId::of(b"moon");
+        let mut record = id.as_bytes().to_vec();
+        ciborium::ser::into_writer(&json!({"tags": [{"Exif": {"tag": "Model", "value": "D50"}}], "form": "Photo"}), &mut record).unwrap();
+        let mut data = MAGIC.to_vec();
+        data.extend(1u16.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend((record.len() as u32).to_le_bytes());
+        data.extend(crc32fast::hash(record.as_slice()).to_le_bytes());
+        data.extend(record);
+        let catalog = decode(path, &data).unwrap();
+        assert!(catalog[&id].tags == HashSet::from([Tag::Exif {tag: "Model".into(), value: "D50".into(), ifd: 0}]));
+    }
+}
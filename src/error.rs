@@ -0,0 +1,66 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Everything that can go wrong loading, saving, importing and exporting objects.
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        message: String, // What is wrong with the file, e.g. a catalog that isn't valid JSON.
+    },
+    Version {
+        path: PathBuf,
+        version: u16, // Newer than this program supports.
+    },
+    Decode(String), // Data that can't be decoded as the form it was identified as.
+    Identify(String),
+    Panic(String), // A bug, e.g. in a decoder given data it doesn't expect.
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    // For map_err: `fs::read(&path).map_err(Error::io(&path))`.
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io {path: path.to_path_buf(), source}
+    }
+
+    pub fn parse<E: Display>(path: &Path) -> impl FnOnce(E) -> Error + '_ {
+        move |e| Error::Parse {path: path.to_path_buf(), message: e.to_string()}
+    }
+}
+
+// What a panic was raised with, as caught by `catch_unwind`.
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or("unknown panic".into())
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io {path, source} => write!(f, "{}: {source}", path.display()),
+            Error::Parse {path, message} => write!(f, "{} is damaged: {message}", path.display()),
+            Error::Version {path, version} => write!(f, "{} was written by a newer version of this program (version {version})", path.display()),
+            Error::Decode(message) => write!(f, "could not decode: {message}"),
+            Error::Identify(message) => write!(f, "could not identify: {message}"),
+            Error::Panic(message) => write!(f, "crashed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io {source, ..} => Some(source),
+            _ => None,
+        }
+    }
+}
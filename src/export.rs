@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::object::{Form, MimeType, Object, ObjectId, Tag};
+use crate::store::ObjectHandle;
+
+// What is written next to an exported file, so the tags aren't lost outside the store.
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    id: &'a ObjectId,
+    form: &'a Form,
+    mime: Option<String>,
+    tags: &'a HashSet<Tag>,
+}
+
+// The sidecar of a file is the file's name with ".json" appended, e.g. "photo.png.json".
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+// Copies the data of an object to `path` and writes its tags to the sidecar next to it.
+pub fn export_object(object: &ObjectHandle, path: &Path) -> Result<()> {
+    let mut data = object.data.open()?;
+    File::create(path).and_then(|mut file| io::copy(&mut data, &mut file)).map_err(Error::io(path))?;
+    let sidecar_path = sidecar_path(path);
+    let sidecar = Sidecar {id: &object.id, form: &object.form, mime: object.mime().map(MimeType::to_string), tags: &object.tags};
+    let sidecar = serde_json::to_vec_pretty(&sidecar).map_err(Error::parse(&sidecar_path))?;
+    fs::write(&sidecar_path, sidecar).map_err(Error::io(&sidecar_path))
+}
+
+// A file name for an object: its title, or its id if it has none, with the extension of its format.
+pub fn file_name<D>(object: &Object<D>) -> String {
+    format!("{}.{}", file_stem(object), object.extension())
+}
+
+fn file_stem<D>(object: &Object<D>) -> String {
+    let title = object.tags.iter().find_map(|tag| match tag {
+        Tag::Title(title) => Some(sanitize(title)),
+        _ => None,
+    });
+    title.filter(|title| !title.is_empty()).unwrap_or(object.id.to_string())
+}
+
+// Replaces the characters that aren't allowed in file names on some system.
+fn sanitize(name: &str) -> String {
+    let name = name.chars().map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) {'_'} else {c}).collect::<String>();
+    name.trim().trim_matches('.').to_string()
+}
+
+// Exports objects into `dir`, numbering the names of objects whose file would already exist.
+// Returns the paths the objects were written to.
+pub fn export_objects<'a>(objects: impl IntoIterator<Item = &'a ObjectHandle>, dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir).map_err(Error::io(dir))?;
+    let mut paths = vec![];
+    for object in objects {
+        let mut path = dir.join(file_name(object));
+        let mut n = 2;
+        while path.exists() {
+            path = dir.join(format!("{} ({n}).{}", file_stem(object), object.extension()));
+            n += 1;
+        }
+        export_object(object, &path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::Store;
+    use crate::store::tests::TempDir;
+    use super::*;
+
+    fn object(data: &[u8], form: Form, tags: impl IntoIterator<Item = Tag>) -> Object {
+        Object {id: ObjectId::of(data), data: data.to_vec(), tags: tags.into_iter().collect(), form}
+    }
+
+    #[test]
+    fn names() {
+        assert!(sanitize("a/b\\c:d*e?f\"g<h>i|j") == "a_b_c_d_e_f_g_h_i_j");
+        assert!(sanitize(" Moon\nRiver ") == "Moon_River");
+        assert!(sanitize("...hidden.") == "hidden");
+        assert!(sanitize("Été à Paris") == "Été à Paris");
+        let moon = object(b"moon", Form::Photo, [Tag::Title("Moon: 1/2".into()), Tag::Mime {mime: MimeType::new("image", "png"), extension: Some("png".into())}]);
+        assert!(file_name(&moon) == "Moon_ 1_2.png");
+        // Without a usable title the id names the file.
+        let untitled = object(b"data", Form::PlainText, []);
+        assert!(file_name(&untitled) == format!("{}.txt", untitled.id));
+        let dots = object(b"bin", Form::Binary, [Tag::Title("..".into())]);
+        assert!(file_name(&dots) == format!("{}.bin", dots.id));
+    }
+
+    #[test]
+    fn names_are_numbered() {
+        let dir = TempDir::new("export");
+        let mut store = Store::open(&dir.0.join("store")).unwrap();
+        let title = || [Tag::Title("Moon".into())];
+        let objects = [b"one".as_slice(), b"two", b"three"].map(|data| store.put(&object(data, Form::PlainText, title())).unwrap());
+        let out = dir.0.join("out");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("Moon (2).txt"), "already there").unwrap();
+        let paths = export_objects(&objects, &out).unwrap();
+        assert!(paths == [out.join("Moon.txt"), out.join("Moon (3).txt"), out.join("Moon (4).txt")]);
+        assert!(fs::read(&paths[2]).unwrap() == b"three" && fs::read(out.join("Moon (2).txt")).unwrap() == b"already there");
+        let sidecar: serde_json::Value = serde_json::from_slice(&fs::read(sidecar_path(&paths[0])).unwrap()).unwrap();
+        assert!(sidecar["id"] == objects[0].id.to_string() && sidecar["form"] == "PlainText" && sidecar["tags"] == serde_json::json!([{"Title": "Moon"}]));
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::error::{panic_message, Error, Result};
+use crate::index;
+use crate::library::Library;
+use crate::magic_identify::{Candidate, Registry};
+use crate::object::{DateConcerns, DateTime, Form, MergePolicy, Object, ObjectId, Tag};
+use crate::object::builders::*;
+use crate::store::{self, ObjectHandle};
+
+// How much of the start of a file is read to identify it. The rest is only ever copied into the
+// store, so files of any size can be imported.
+const IDENTIFY_BYTES: u64 = 64 * 1024;
+
+// What to do with symbolic links found while importing a directory.
+#[derive(Eq, PartialEq, Clone, Copy, Default)]
+pub enum SymlinkPolicy {
+    #[default]
+    Skip,
+    FollowFiles, // Import linked files, but don't descend into linked directories.
+    Follow, // Follow every link. Directories that were already visited are skipped.
+}
+
+// What importing some data did. Data that is already stored isn't added again; its tags are
+// merged into the stored object's instead, and the duplicate is reported with the tags it brought
+// and those the merge left out.
+pub enum Imported {
+    New(ObjectId),
+    Duplicate {id: ObjectId, kept: Vec<Tag>, dropped: Vec<Tag>},
+}
+
+impl Imported {
+    pub fn id(&self) -> ObjectId {
+        match self {
+            Imported::New(id) | Imported::Duplicate {id, ..} => *id,
+        }
+    }
+}
+
+pub fn import_file(path: &str, library: &mut Library, policy: MergePolicy) -> Result<Imported> {
+    let path = Path::new(path);
+    let categories = path.parent().and_then(Path::file_name).map(|name| name.to_string_lossy().to_string());
+    let object = receive_file(path, categories.as_slice(), library.identifiers(), library.root())?;
+    insert_imported(object, library, policy)
+}
+
+// Imports a file, or every file under a directory. Returns the outcome for each file that was tried.
+pub fn import_path(path: &Path, library: &mut Library, policy: MergePolicy, symlinks: SymlinkPolicy) -> Vec<(PathBuf, Result<Imported>)> {
+    scan(path, symlinks).into_iter().map(|(path, categories)| {
+        let object = categories.and_then(|categories| receive_file(&path, categories.as_slice(), library.identifiers(), library.root()));
+        let id = object.and_then(|object| insert_imported(object, library, policy));
+        (path, id)
+    }).collect()
+}
+
+// Lists a file, or every file under a directory, along with its categories: the names of the
+// directories from `path` down to the file. Directories that can't be read are listed with the error.
+pub fn scan(path: &Path, symlinks: SymlinkPolicy) -> Vec<(PathBuf, Result<Vec<String>>)> {
+    let mut found = vec![];
+    if path.is_dir() {
+        let categories = path.canonicalize().ok().and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()));
+        scan_directory(path, categories.into_iter().collect(), symlinks, &mut HashSet::new(), &mut found);
+    } else {
+        let categories = path.parent().and_then(Path::file_name).map(|name| name.to_string_lossy().to_string());
+        found.push((path.to_path_buf(), Ok(categories.into_iter().collect())));
+    }
+    found
+}
+
+fn scan_directory(dir: &Path, categories: Vec<String>, symlinks: SymlinkPolicy, visited: &mut HashSet<PathBuf>, found: &mut Vec<(PathBuf, Result<Vec<String>>)>) {
+    if let Ok(canonical) = dir.canonicalize() {
+        if !visited.insert(canonical) {
+            return;
+        }
+    }
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect::<Vec<_>>(),
+        Err(e) => {
+            found.push((dir.to_path_buf(), Err(Error::io(dir)(e))));
+            return;
+        }
+    };
+    entries.sort();
+    for entry in entries {
+        let is_link = fs::symlink_metadata(&entry).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false);
+        if entry.is_dir() {
+            if is_link && symlinks != SymlinkPolicy::Follow {
+                continue;
+            }
+            let mut categories = categories.clone();
+            categories.extend(entry.file_name().map(|name| name.to_string_lossy().to_string()));
+            scan_directory(&entry, categories, symlinks, visited, found);
+        } else {
+            if is_link && symlinks == SymlinkPolicy::Skip {
+                continue;
+            }
+            found.push((entry, Ok(categories.clone())));
+        }
+    }
+}
+
+// A file that was copied into a store, ready to be added to its library with `insert_imported`.
+pub struct Received {
+    pub object: ObjectHandle,
+    pub words: HashSet<String>, // Of a plain text, for the search index.
+}
+
+// Copies a file into the store at `store` and makes an object of it, tagged with its name, the
+// given categories and its timestamps. This doesn't touch the library, so it can run on any
+// thread.
+pub fn receive_file(path: &Path, categories: &[String], identifiers: &Registry, store: &Path) -> Result<Received> {
+    let mut file = File::open(path).map_err(Error::io(path))?;
+    let metadata = file.metadata().map_err(Error::io(path))?;
+    let mut prefix = vec![];
+    (&mut file).take(IDENTIFY_BYTES).read_to_end(&mut prefix).map_err(Error::io(path))?;
+    let candidate = identify(identifiers, prefix.as_slice(), metadata.len())?;
+    file.rewind().map_err(Error::io(path))?;
+    let (id, data) = store::receive(store, &mut file)?;
+    let mut object = Object {id, data, tags: HashSet::new(), form: form(&candidate)};
+    tag_identified(&mut object, candidate);
+    let mut words = HashSet::new();
+    match object.form {
+        Form::Photo => {
+            file.rewind().map_err(Error::io(path))?;
+            add_exif(&mut object, &mut BufReader::new(file));
+        }
+        Form::PlainText => {
+            file.rewind().map_err(Error::io(path))?;
+            words = index::words(file);
+        }
+        _ => {}
+    }
+    if let Some(title) = path.file_stem() {
+        object.tags.insert(Tag::Title(title.to_string_lossy().to_string()));
+    }
+    for category in categories {
+        object.tags.insert(Tag::Category(category.clone()));
+    }
+    // Dates found in the data itself, such as EXIF's, take precedence over the file's.
+    if let Ok(created) = metadata.created() {
+        object.add_tag(Tag::Date {value: DateTime::from_system_time(created), concerns: DateConcerns::Created}).ok();
+    }
+    if let Ok(modified) = metadata.modified() {
+        object.add_tag(Tag::Date {value: DateTime::from_system_time(modified), concerns: DateConcerns::Edited}).ok();
+    }
+    Ok(Received {object, words})
+}
+
+pub fn import_file_bytes(data: Vec<u8>, library: &mut Library, policy: MergePolicy) -> Result<Imported> {
+    let mut object = build_object(data, library.identifiers())?;
+    date_added(&mut object, library);
+    let (id, incoming) = (object.id, object.tags.clone());
+    let existing = library.get(&id).map(|existing| existing.tags.clone());
+    library.insert(object, policy)?;
+    Ok(report(library, id, existing, incoming))
+}
+
+// Identifies the data and builds an object of the form it was identified as.
+pub fn build_object(data: Vec<u8>, identifiers: &Registry) -> Result<Object> {
+    let candidate = identify(identifiers, data.as_slice(), data.len() as u64)?;
+    let mut object = match form(&candidate) {
+        Form::PlainText => plain_text(String::from_utf8_lossy(data.as_slice()).to_string()),
+        Form::TypesetText => typeset_text(data),
+        Form::App => app(data),
+        Form::Photo => photo(data),
+        Form::Sound => sound(data),
+        Form::Video => video(data),
+        Form::Model3D => model3d(data),
+        Form::Archive => archive(data),
+        _ => binary(data),
+    };
+    tag_identified(&mut object, candidate);
+    Ok(object)
+}
+
+// Identifiers can be registered from outside this crate, so one that panics fails the import
+// rather than the program.
+fn identify(identifiers: &Registry, prefix: &[u8], length: u64) -> Result<Candidate> {
+    catch_unwind(AssertUnwindSafe(|| identifiers.best_prefix(prefix, length)))
+        .map_err(|panic| Error::Identify(panic_message(panic.as_ref())))
+}
+
+// The forms objects are built as. Data of any other form is kept as binary.
+fn form(candidate: &Candidate) -> Form {
+    match candidate.form {
+        Form::PlainText | Form::TypesetText | Form::App | Form::Photo | Form::Sound | Form::Video | Form::Model3D | Form::Archive => candidate.form.clone(),
+        _ => Form::Binary,
+    }
+}
+
+fn tag_identified<D>(object: &mut Object<D>, candidate: Candidate) {
+    object.tags.insert(Tag::IdentifiedBy {identifier: candidate.identifier, confidence: (candidate.confidence * 100.0).round() as u8});
+    if let Some(mime) = candidate.mime {
+        object.tags.insert(Tag::Mime {extension: mime.extension().map(String::from), mime});
+    }
+}
+
+pub fn insert_imported(received: Received, library: &mut Library, policy: MergePolicy) -> Result<Imported> {
+    let Received {mut object, words} = received;
+    date_added(&mut object, library);
+    let (id, incoming) = (object.id, object.tags.clone());
+    let existing = library.get(&id).map(|existing| existing.tags.clone());
+    library.insert_received(object, words, policy)?;
+    Ok(report(library, id, existing, incoming))
+}
+
+// Compares the tags an object had before an import and those the import brought with the tags it
+// ended up with.
+fn report(library: &Library, id: ObjectId, existing: Option<HashSet<Tag>>, incoming: HashSet<Tag>) -> Imported {
+    let (Some(existing), Some(merged)) = (existing, library.get(&id)) else {
+        return Imported::New(id);
+    };
+    let mut kept = merged.tags.difference(&existing).cloned().collect::<Vec<_>>();
+    let mut dropped = existing.union(&incoming).filter(|tag| !merged.tags.contains(tag)).cloned().collect::<Vec<_>>();
+    kept.sort_by_key(Tag::to_string);
+    dropped.sort_by_key(Tag::to_string);
+    Imported::Duplicate {id, kept, dropped}
+}
+
+// Objects are dated when they are first added; importing the same data again keeps that date.
+fn date_added<D>(object: &mut Object<D>, library: &Library) {
+    match library.get(&object.id) {
+        Some(existing) => {
+            let added = existing.tags.iter().filter(|tag| matches!(tag, Tag::Date {concerns: DateConcerns::Added, ..})).cloned().collect::<Vec<_>>();
+            object.tags.extend(added);
+        }
+        None => {
+            object.tags.insert(Tag::Date {value: DateTime::from_system_time(SystemTime::now()), concerns: DateConcerns::Added});
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::tests::TempDir;
+    use super::*;
+
+    #[test]
+    fn files_are_received() {
+        let dir = TempDir::new("receive-file");
+        let store = dir.0.join("store");
+        store::Store::open(&store).unwrap();
+        fs::create_dir_all(dir.0.join("notes")).unwrap();
+        let text = dir.0.join("notes").join("song.txt");
+        fs::write(&text, "Tea for two, and two for tea").unwrap();
+        let Received {object, words} = receive_file(&text, &["notes".into()], &Registry::default(), &store).unwrap();
+        assert!(object.id == ObjectId::of(b"Tea for two, and two for tea") && object.form == Form::PlainText);
+        assert!(object.data.read().unwrap() == b"Tea for two, and two for tea");
+        assert!(object.tags.contains(&Tag::Title("song".into())) && object.tags.contains(&Tag::Category("notes".into())));
+        assert!(object.tags.iter().any(|tag| matches!(tag, Tag::Date {concerns: DateConcerns::Edited, ..})));
+        assert!(words == HashSet::from(["tea".into(), "for".into(), "two".into(), "and".into()]));
+        // Binary data isn't indexed.
+        let binary = dir.0.join("data.bin");
+        fs::write(&binary, [0, 159, 146, 150, 0, 1]).unwrap();
+        let received = receive_file(&binary, &[], &Registry::default(), &store).unwrap();
+        assert!(received.object.form == Form::Binary && received.words.is_empty());
+        assert!(receive_file(&dir.0.join("missing"), &[], &Registry::default(), &store).is_err());
+    }
+
+    // The files found under `root`, relative to it, with their categories.
+    fn scanned(root: &Path, symlinks: SymlinkPolicy) -> Vec<(String, Vec<String>)> {
+        scan(root, symlinks).into_iter()
+            .map(|(path, categories)| (path.strip_prefix(root).unwrap().to_string_lossy().to_string(), categories.ok().unwrap()))
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directories_are_scanned() {
+        use std::os::unix::fs::symlink;
+        let dir = TempDir::new("scan");
+        let root = dir.0.join("photos");
+        fs::create_dir_all(root.join("trips").join("paris")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("trips").join("paris").join("b.txt"), "b").unwrap();
+        symlink(root.join("trips").join("paris").join("b.txt"), root.join("link_file")).unwrap();
+        symlink(root.join("trips"), root.join("link_dir")).unwrap();
+        symlink(&root, root.join("trips").join("loop")).unwrap();
+        let categories = |categories: &[&str]| categories.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(scanned(&root, SymlinkPolicy::Skip) == [
+            ("a.txt".into(), categories(&["photos"])),
+            ("trips/paris/b.txt".into(), categories(&["photos", "trips", "paris"])),
+        ]);
+        assert!(scanned(&root, SymlinkPolicy::FollowFiles) == [
+            ("a.txt".into(), categories(&["photos"])),
+            ("link_file".into(), categories(&["photos"])),
+            ("trips/paris/b.txt".into(), categories(&["photos", "trips", "paris"])),
+        ]);
+        // Each directory is scanned once, under the first path it is found by, and the link back
+        // up to the top isn't followed round.
+        assert!(scanned(&root, SymlinkPolicy::Follow) == [
+            ("a.txt".into(), categories(&["photos"])),
+            ("link_dir/paris/b.txt".into(), categories(&["photos", "link_dir", "paris"])),
+            ("link_file".into(), categories(&["photos"])),
+        ]);
+        // A file on its own is in the category of its directory.
+        assert!(scan(&root.join("a.txt"), SymlinkPolicy::Skip).into_iter().map(|(path, categories)| (path, categories.ok().unwrap())).collect::<Vec<_>>()
+            == [(root.join("a.txt"), categories(&["photos"]))]);
+        // So is a directory given by a path that doesn't end in its name.
+        assert!(scanned(&root.join("trips").join(".."), SymlinkPolicy::Skip) == scanned(&root, SymlinkPolicy::Skip));
+    }
+}
@@ -0,0 +1,368 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::io::Read;
+use std::ops::Bound;
+use crate::object::{DateTime, Form, Location, Object, ObjectId, Tag};
+use crate::query::{tag_value, Field, Op, Query, Term};
+
+// What an object was indexed under, so it can be taken out again without its data.
+struct Posting {
+    form: Form,
+    tags: Vec<Tag>,
+    words: Vec<String>,
+    text: HashSet<String>, // The words of a plain text, kept so that retagging needn't read it again.
+}
+
+// Maps forms, tag values and words of tag values and plain texts to the objects that have them.
+// Queries are answered by looking up these maps, without looking at object data or at every tag.
+#[derive(Default)]
+pub struct Index {
+    postings: HashMap<ObjectId, Posting>,
+    forms: HashMap<Form, HashSet<ObjectId>>,
+    // Tag values by the field they are searched under, both in lower case as fields are compared
+    // without case, e.g. "exif.model" -> "canon eos 5d". Dates are kept apart, by their concerns.
+    values: HashMap<String, BTreeMap<String, HashSet<ObjectId>>>,
+    dates: HashMap<String, HashMap<DateTime, HashSet<ObjectId>>>,
+    locations: HashMap<Location, HashSet<ObjectId>>,
+    words: BTreeMap<String, HashSet<ObjectId>>,
+}
+
+// How much of a plain text is indexed, so that indexing a huge one doesn't load it whole.
+pub const INDEX_BYTES: u64 = 1024 * 1024;
+
+// The words of the start of a plain text. Data that can't be read is indexed as far as it was.
+pub fn words(data: impl Read) -> HashSet<String> {
+    let mut text = vec![];
+    let _ = data.take(INDEX_BYTES).read_to_end(&mut text);
+    tokenize(String::from_utf8_lossy(text.as_slice()).as_ref()).collect()
+}
+
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_lowercase)
+}
+
+impl Index {
+    // `text` holds the words of a plain text object, see `words`, which are indexed along with
+    // its tags.
+    pub fn insert<D>(&mut self, object: &Object<D>, text: HashSet<String>) {
+        self.remove(&object.id);
+        let mut words = text.clone();
+        for tag in &object.tags {
+            words.extend(tokenize(tag_value(tag).as_str()));
+        }
+        self.forms.entry(object.form.clone()).or_default().insert(object.id);
+        for tag in &object.tags {
+            match tag {
+                Tag::Date {value, concerns} => {
+                    self.dates.entry(concerns.to_string().to_lowercase()).or_default().entry(value.clone()).or_default().insert(object.id);
+                }
+                Tag::Location(location) => {
+                    self.locations.entry(location.clone()).or_default().insert(object.id);
+                }
+                _ => {}
+            }
+            for (field, value) in fields(tag) {
+                self.values.entry(field).or_default().entry(value).or_default().insert(object.id);
+            }
+        }
+        for word in &words {
+            self.words.entry(word.clone()).or_default().insert(object.id);
+        }
+        self.postings.insert(object.id, Posting {
+            form: object.form.clone(),
+            tags: object.tags.iter().cloned().collect(),
+            words: words.into_iter().collect(),
+            text,
+        });
+    }
+
+    // Indexes the tags and form of an object again, keeping the words of its text.
+    pub fn retag<D>(&mut self, object: &Object<D>) {
+        let text = self.postings.get(&object.id).map(|posting| posting.text.clone()).unwrap_or_default();
+        self.insert(object, text);
+    }
+
+    pub fn remove(&mut self, id: &ObjectId) {
+        let Some(posting) = self.postings.remove(id) else { return };
+        unlink(&mut self.forms, &posting.form, id);
+        for tag in &posting.tags {
+            match tag {
+                Tag::Date {value, concerns} => {
+                    let concerns = concerns.to_string().to_lowercase();
+                    if let Some(dates) = self.dates.get_mut(&concerns) {
+                        unlink(dates, value, id);
+                        if dates.is_empty() {
+                            self.dates.remove(&concerns);
+                        }
+                    }
+                }
+                Tag::Location(location) => unlink(&mut self.locations, location, id),
+                _ => {}
+            }
+            for (field, value) in fields(tag) {
+                if let Some(values) = self.values.get_mut(&field) {
+                    unlink_sorted(values, &value, id);
+                    if values.is_empty() {
+                        self.values.remove(&field);
+                    }
+                }
+            }
+        }
+        for word in &posting.words {
+            unlink_sorted(&mut self.words, word, id);
+        }
+    }
+
+    pub fn search(&self, query: &Query) -> HashSet<ObjectId> {
+        match query {
+            Query::All => self.postings.keys().cloned().collect(),
+            Query::And(a, b) => {
+                let a = self.search(a);
+                let b = self.search(b);
+                a.intersection(&b).cloned().collect()
+            }
+            Query::Or(a, b) => {
+                let mut a = self.search(a);
+                a.extend(self.search(b));
+                a
+            }
+            Query::Not(a) => {
+                let a = self.search(a);
+                self.postings.keys().filter(|id| !a.contains(id)).cloned().collect()
+            }
+            Query::Term(Term::Form(form)) => self.forms.get(form).cloned().unwrap_or_default(),
+            // Dates compare as far as both are known, so those of the field are all compared.
+            Query::Term(Term::Field {field: Field::Date(concerns), op, value}) => {
+                let concerns = concerns.as_ref().map(|concerns| concerns.to_string().to_lowercase());
+                let other = value.parse::<DateTime>().ok();
+                self.dates.iter().filter(|(c, _)| concerns.is_none() || concerns.as_ref() == Some(*c))
+                    .flat_map(|(_, dates)| dates)
+                    .filter(|(date, _)| {
+                        other.as_ref().is_some_and(|other| compare_ordering(date.cmp_prefix(other), *op))
+                            || (*op == Op::Contains && date.to_string().contains(value.as_str()))
+                    })
+                    .flat_map(|(_, ids)| ids.iter().cloned()).collect()
+            }
+            Query::Term(Term::Field {field, op, value}) => {
+                let Some(values) = self.values.get(&field_name(field)) else { return HashSet::new() };
+                let value = value.to_lowercase();
+                let found: Box<dyn Iterator<Item = (&String, &HashSet<ObjectId>)>> = match op {
+                    Op::Is => Box::new(values.get_key_value(&value).into_iter()),
+                    Op::Lt => Box::new(values.range(..value)),
+                    Op::Le => Box::new(values.range(..=value)),
+                    Op::Gt => Box::new(values.range((Bound::Excluded(value), Bound::Unbounded))),
+                    Op::Ge => Box::new(values.range(value..)),
+                    // Only the values of the field are looked at.
+                    Op::Contains => Box::new(values.iter().filter(move |(s, _)| s.contains(value.as_str()))),
+                };
+                found.flat_map(|(_, ids)| ids.iter().cloned()).collect()
+            }
+            Query::Term(Term::Area(area)) => {
+                self.locations.iter().filter(|(location, _)| area.contains(location)).flat_map(|(_, ids)| ids.iter().cloned()).collect()
+            }
+            // Each word of the term has to start a word of the object. A term without words, like
+            // "-", doesn't narrow the search.
+            Query::Term(Term::Text(text)) => {
+                let mut result = self.postings.keys().cloned().collect::<HashSet<_>>();
+                for part in tokenize(text) {
+                    let ids = self.words.range(part.clone()..).take_while(|(word, _)| word.starts_with(part.as_str()))
+                        .flat_map(|(_, ids)| ids.iter().cloned()).collect::<HashSet<_>>();
+                    result.retain(|id| ids.contains(id));
+                }
+                result
+            }
+        }
+    }
+}
+
+// The fields other than dates that a tag is searched under, with its value for each.
+fn fields(tag: &Tag) -> Vec<(String, String)> {
+    let field = |field: Field, value: &str| (field_name(&field), value.to_lowercase());
+    match tag {
+        Tag::Title(s) => vec![field(Field::Title, s)],
+        Tag::Author(s) => vec![field(Field::Author, s)],
+        Tag::Category(s) => vec![field(Field::Category, s)],
+        Tag::IdentifiedBy {identifier, ..} => vec![field(Field::Identifier, identifier)],
+        Tag::Mime {mime, extension} => {
+            let mut fields = vec![field(Field::Mime, mime.to_string().as_str())];
+            fields.extend(extension.iter().map(|extension| field(Field::Extension, extension)));
+            fields
+        }
+        Tag::Exif {tag, value, ..} => vec![field(Field::Exif(tag.clone()), value)],
+        Tag::OtherUnknown {tag, value} => vec![field(Field::Other(tag.clone()), value)],
+        Tag::Location(location) => location.place().map(|place| field(Field::Place, place)).into_iter().collect(),
+        Tag::Date {..} => vec![],
+    }
+}
+
+fn field_name(field: &Field) -> String {
+    match field {
+        Field::Title => "title".into(),
+        Field::Author => "author".into(),
+        Field::Category => "category".into(),
+        Field::Identifier => "identifier".into(),
+        Field::Mime => "mime".into(),
+        Field::Extension => "ext".into(),
+        Field::Place => "place".into(),
+        Field::Exif(tag) => format!("exif.{}", tag.to_lowercase()),
+        Field::Date(concerns) => format!("date.{}", concerns.as_ref().map(|concerns| concerns.to_string().to_lowercase()).unwrap_or_default()),
+        Field::Other(tag) => format!("other.{}", tag.to_lowercase()),
+    }
+}
+
+fn compare_ordering(ordering: Ordering, op: Op) -> bool {
+    match op {
+        Op::Is => ordering == Ordering::Equal,
+        Op::Contains => false,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+    }
+}
+
+fn unlink<K: Eq + Hash>(map: &mut HashMap<K, HashSet<ObjectId>>, key: &K, id: &ObjectId) {
+    if let Some(ids) = map.get_mut(key) {
+        ids.remove(id);
+        if ids.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+fn unlink_sorted<K: Ord>(map: &mut BTreeMap<K, HashSet<ObjectId>>, key: &K, id: &ObjectId) {
+    if let Some(ids) = map.get_mut(key) {
+        ids.remove(id);
+        if ids.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object::{DateConcerns, MimeType};
+    use crate::query::parse_location;
+    use super::*;
+
+    fn object(name: &str, form: Form, tags: impl IntoIterator<Item = Tag>) -> Object<()> {
+        Object {id: ObjectId::of(name.as_bytes()), data: (), tags: tags.into_iter().collect(), form}
+    }
+
+    fn date(value: &str, concerns: DateConcerns) -> Tag {
+        Tag::Date {value: value.parse::<DateTime>().ok().unwrap(), concerns}
+    }
+
+    // The names of the objects found, sorted.
+    fn search(index: &Index, objects: &[&Object<()>], query: &str) -> Vec<String> {
+        let found = index.search(&Query::parse(query).unwrap());
+        let mut names = ["moon", "note", "sea"].into_iter().zip(objects).filter(|(_, object)| found.contains(&object.id)).map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn library() -> (Index, [Object<()>; 3]) {
+        let moon = object("moon", Form::Photo, [
+            Tag::Title("Moon River".into()),
+            Tag::Mime {mime: MimeType::new("image", "jpeg"), extension: Some("JPG".into())},
+            Tag::Exif {tag: "Model".into(), value: "Canon EOS 5D".into(), ifd: 0},
+            date("2020-01-15T22:10", DateConcerns::Created),
+            Tag::Location(parse_location("48.8584,2.2945,Paris").unwrap()),
+        ]);
+        let note = object("note", Form::PlainText, [Tag::Title("Shopping".into()), date("2021-03-02", DateConcerns::Added)]);
+        let sea = object("sea", Form::Photo, [Tag::Title("Sea".into()), Tag::Author("Jane Doe".into()), date("2019-07", DateConcerns::Created)]);
+        let mut index = Index::default();
+        index.insert(&moon, HashSet::new());
+        index.insert(&note, words("Milk, bread & honey-moon cake".as_bytes()));
+        index.insert(&sea, HashSet::new());
+        (index, [moon, note, sea])
+    }
+
+    #[test]
+    fn text() {
+        let (index, [moon, note, sea]) = library();
+        let search = |query| search(&index, &[&moon, &note, &sea], query);
+        assert!(search("moon") == ["moon", "note"]);
+        // Words are found by how they start, in any order, in tags and texts alike.
+        assert!(search("\"River Moon\"") == ["moon"]);
+        assert!(search("riv") == ["moon"]);
+        assert!(search("iver").is_empty());
+        assert!(search("BREAD") == ["note"]);
+        assert!(search("jane") == ["sea"]);
+        // A term without words doesn't narrow the search.
+        assert!(search("-") == ["moon", "note", "sea"]);
+        assert!(search("NOT -").is_empty());
+    }
+
+    #[test]
+    fn fields() {
+        let (index, [moon, note, sea]) = library();
+        let search = |query| search(&index, &[&moon, &note, &sea], query);
+        assert!(search("title:\"moon river\"") == ["moon"]);
+        assert!(search("title:moon").is_empty());
+        assert!(search("title:~RIVER") == ["moon"]);
+        assert!(search("title<s") == ["moon"]);
+        assert!(search("title>=sea") == ["note", "sea"]);
+        assert!(search("title>sea") == ["note"]);
+        assert!(search("ext:jpg mime:image/jpeg") == ["moon"]);
+        assert!(search("exif.model:~canon") == ["moon"]);
+        assert!(search("author:\"Jane Doe\"") == ["sea"]);
+        assert!(search("place:paris") == ["moon"]);
+        assert!(search("form:photo") == ["moon", "sea"]);
+        assert!(search("form:text") == ["note"]);
+        assert!(search("missing:thing").is_empty());
+    }
+
+    #[test]
+    fn dates_and_places() {
+        let (index, [moon, note, sea]) = library();
+        let search = |query| search(&index, &[&moon, &note, &sea], query);
+        assert!(search("date:2020") == ["moon"]);
+        assert!(search("date.created>=2019-07-31") == ["moon", "sea"]);
+        assert!(search("date.created<2020") == ["sea"]);
+        assert!(search("date.added<2022") == ["note"]);
+        assert!(search("date>2020-01-15T12:00") == ["moon", "note"]);
+        assert!(search("date:~-03-") == ["note"]);
+        assert!(search("near:48.86,2.29,2km") == ["moon"]);
+        assert!(search("within:40,0,50,10") == ["moon"]);
+        assert!(search("near:0,0,100km").is_empty());
+    }
+
+    #[test]
+    fn operators() {
+        let (index, [moon, note, sea]) = library();
+        let search = |query| search(&index, &[&moon, &note, &sea], query);
+        assert!(search("") == ["moon", "note", "sea"]);
+        assert!(search("moon form:photo") == ["moon"]);
+        assert!(search("moon AND sea").is_empty());
+        assert!(search("moon OR sea") == ["moon", "note", "sea"]);
+        assert!(search("NOT moon") == ["sea"]);
+        assert!(search("form:photo AND NOT (jane OR date:2021)") == ["moon"]);
+    }
+
+    #[test]
+    fn changes() {
+        let (mut index, [mut moon, mut note, sea]) = library();
+        moon.tags.retain(|tag| !matches!(tag, Tag::Title(_)));
+        moon.tags.insert(Tag::Title("Crater".into()));
+        index.retag(&moon);
+        note.tags.clear();
+        index.retag(&note);
+        {
+            let search = |query| search(&index, &[&moon, &note, &sea], query);
+            assert!(search("river").is_empty());
+            assert!(search("crater") == ["moon"]);
+            // The words of a text are kept when it is retagged.
+            assert!(search("moon") == ["note"]);
+            assert!(search("shopping OR date.added:2021").is_empty());
+        }
+        for object in [&moon, &note, &sea] {
+            index.remove(&object.id);
+        }
+        index.remove(&sea.id);
+        assert!(index.search(&Query::All).is_empty());
+        assert!(index.postings.is_empty() && index.forms.is_empty() && index.values.is_empty());
+        assert!(index.dates.is_empty() && index.locations.is_empty() && index.words.is_empty());
+    }
+}
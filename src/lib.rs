@@ -0,0 +1,14 @@
+pub mod catalog;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod index;
+pub mod library;
+pub mod magic_identify;
+pub mod object;
+pub mod query;
+pub mod schema;
+pub mod similar;
+pub mod store;
+pub mod thumbnail;
+pub mod worker;
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use crate::error::Result;
+use crate::index::{words, Index};
+use crate::magic_identify::Registry;
+use crate::object::{Form, MergePolicy, Object, ObjectId, Tag};
+use crate::query::Query;
+use crate::store::{ObjectHandle, Store};
+
+// The objects of a store together with their search index. Every change to the objects goes
+// through here so the index and the store stay up to date. Only the tags and forms are kept in
+// memory; data is read from the store when it is needed.
+pub struct Library {
+    store: Store,
+    objects: HashMap<ObjectId, ObjectHandle>,
+    index: Index,
+    identifiers: Arc<Registry>,
+}
+
+impl Library {
+    pub fn load(store: &str) -> Result<Library> {
+        let mut store = Store::open(Path::new(store))?;
+        let objects = store.handles()?;
+        let mut index = Index::default();
+        for object in objects.values() {
+            // Plain texts from before their words were kept in the store are read once.
+            let words = match store.words(&object.id) {
+                Some(words) => words,
+                None if object.form == Form::PlainText => {
+                    let words = object.data.open().map(words).unwrap_or_default();
+                    store.set_words(&object.id, &words);
+                    words
+                }
+                None => HashSet::new(),
+            };
+            index.insert(object, words);
+        }
+        Ok(Library {store, objects, index, identifiers: Arc::new(Registry::default())})
+    }
+
+    // Where the store is, for receiving data into it from other threads, see store::receive.
+    pub fn root(&self) -> &Path {
+        self.store.root()
+    }
+
+    // The backup that was loaded because the store's catalog couldn't be read.
+    pub fn recovered_from(&self) -> Option<&Path> {
+        self.store.recovered_from()
+    }
+
+    // Data is written as it is added, so this only has to write the tags and forms.
+    pub fn save(&mut self) -> Result<()> {
+        self.store.flush()
+    }
+
+    // The identifiers that imports into this library are classified with. They are shared with
+    // the threads that import in the background.
+    pub fn identifiers(&self) -> &Arc<Registry> {
+        &self.identifiers
+    }
+
+    // None while the identifiers are shared.
+    pub fn identifiers_mut(&mut self) -> Option<&mut Registry> {
+        Arc::get_mut(&mut self.identifiers)
+    }
+
+    pub fn get(&self, id: &ObjectId) -> Option<&ObjectHandle> {
+        self.objects.get(id)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &ObjectHandle> {
+        self.objects.values()
+    }
+
+    // Adds an object, merging its tags into an existing object with the same id. Its data is
+    // written to the store right away.
+    pub fn insert(&mut self, object: Object, policy: MergePolicy) -> Result<ObjectId> {
+        let object = self.merged(object, policy);
+        let text = match object.form {
+            Form::PlainText => words(object.data.as_slice()),
+            _ => HashSet::new(),
+        };
+        let handle = self.store.put(&object)?;
+        self.index_text(&handle, text);
+        Ok(self.add(handle))
+    }
+
+    // Like `insert`, for an object whose data was received into the store with store::receive.
+    // The words of a plain text are read from it beforehand, see index::words, so that this
+    // needn't read any data.
+    pub fn insert_received(&mut self, object: ObjectHandle, text: HashSet<String>, policy: MergePolicy) -> Result<ObjectId> {
+        let object = self.merged(object, policy);
+        let handle = self.store.adopt(&object)?;
+        self.index_text(&handle, text);
+        Ok(self.add(handle))
+    }
+
+    fn index_text(&mut self, object: &ObjectHandle, text: HashSet<String>) {
+        if object.form == Form::PlainText {
+            self.store.set_words(&object.id, &text);
+        }
+        self.index.insert(object, text);
+    }
+
+    fn merged<D>(&self, mut object: Object<D>, policy: MergePolicy) -> Object<D> {
+        if let Some(existing) = self.objects.get(&object.id) {
+            let incoming = std::mem::replace(&mut object.tags, existing.tags.clone());
+            object.merge_tags(incoming, policy);
+        }
+        object
+    }
+
+    fn add(&mut self, handle: ObjectHandle) -> ObjectId {
+        let id = handle.id;
+        self.objects.insert(id, handle);
+        id
+    }
+
+    // Changes the tags of an object in place and reindexes it. Returns false if there is no such
+    // object.
+    pub fn update(&mut self, id: &ObjectId, f: impl FnOnce(&mut ObjectHandle)) -> bool {
+        match self.objects.get_mut(id) {
+            Some(object) => {
+                f(object);
+                self.index.retag(object);
+                self.store.update(object);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: &ObjectId) -> Option<ObjectHandle> {
+        self.index.remove(id);
+        self.store.remove(id);
+        self.objects.remove(id)
+    }
+
+    // Merges the tags of a near duplicate into an object according to the policy, then removes the
+    // duplicate. Its data differs, so only user tags are merged; the object keeps its own MIME
+    // type, EXIF fields and so on. Returns false if either object is missing.
+    pub fn merge_into(&mut self, id: &ObjectId, duplicate: &ObjectId, policy: MergePolicy) -> bool {
+        if id == duplicate || !self.objects.contains_key(id) {
+            return false;
+        }
+        let Some(duplicate) = self.remove(duplicate) else {
+            return false;
+        };
+        let tags = duplicate.tags.into_iter().filter(Tag::is_user_tag).collect::<HashSet<_>>();
+        self.update(id, |object| match policy {
+            MergePolicy::KeepIncoming => {
+                object.tags.retain(|tag| !tag.is_user_tag());
+                object.tags.extend(tags);
+            }
+            _ => object.merge_tags(tags, policy),
+        })
+    }
+
+    pub fn search(&self, query: &Query) -> HashSet<ObjectId> {
+        self.index.search(query)
+    }
+}
+
@@ -1,21 +1,231 @@
+use std::cell::Cell;
 use nom::branch::alt;
-use nom::bytes::complete::tag;
+use nom::bytes::complete::{tag, take};
 use nom::IResult;
-use nom::combinator::fail;
-use object::Form;
+use nom::combinator::{fail, verify};
+use nom::number::complete::le_u32;
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+use object::{Form, MimeType};
 use crate::object;
 
 pub fn magic_identify(input: &[u8]) -> Form {
-    let result = alt((magic_photo, magic_plaintext))(input);
-    if result.is_ok() {
-        result.unwrap().1
-    } else {
-        Form::Binary
+    Registry::default().best(input).form
+}
+
+// A form that some data might have, and how sure the identifier that suggested it is.
+#[derive(Clone)]
+pub struct Candidate {
+    pub form: Form,
+    pub confidence: f32, // From 0 to 1.
+    pub mime: Option<MimeType>,
+    pub identifier: String,
+}
+
+pub trait Identifier: Send + Sync {
+    fn name(&self) -> &str;
+    fn identify(&self, input: &[u8]) -> Vec<Candidate>;
+
+    // Identifies data from its first bytes, for data too large to look at whole. `length` is the
+    // length of all of it. Most signatures are at the start, so by default the prefix is
+    // identified as if it were the whole.
+    fn identify_prefix(&self, prefix: &[u8], length: u64) -> Vec<Candidate> {
+        let _ = length;
+        self.identify(prefix)
+    }
+}
+
+// Identifies data by its signature, using one of the parsers below.
+pub struct Magic {
+    pub name: &'static str,
+    pub parser: fn(&[u8]) -> IResult<&[u8], Form>,
+    pub confidence: f32,
+    pub mime: (&'static str, &'static str),
+}
+
+impl Magic {
+    pub fn new(name: &'static str, parser: fn(&[u8]) -> IResult<&[u8], Form>, confidence: f32, kind: &'static str, subtype: &'static str) -> Magic {
+        Magic {name, parser, confidence, mime: (kind, subtype)}
+    }
+}
+
+impl Identifier for Magic {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn identify(&self, input: &[u8]) -> Vec<Candidate> {
+        match (self.parser)(input) {
+            Ok((_, form)) => vec![Candidate {form, confidence: self.confidence, mime: Some(MimeType::new(self.mime.0, self.mime.1)), identifier: self.name.into()}],
+            Err(_) => vec![],
+        }
+    }
+}
+
+// Text that is also valid JSON.
+pub struct Json;
+
+impl Identifier for Json {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn identify(&self, input: &[u8]) -> Vec<Candidate> {
+        match serde_json::from_slice::<serde_json::Value>(input) {
+            Ok(serde_json::Value::Object(_)) | Ok(serde_json::Value::Array(_)) => {
+                vec![Candidate {form: Form::PlainText, confidence: 0.8, mime: Some(MimeType::new("application", "json")), identifier: self.name().into()}]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+// A binary STL, whose triangle count has to match the length of the data, so that the whole
+// file needn't be read to check it.
+pub struct StlBinary;
+
+impl Identifier for StlBinary {
+    fn name(&self) -> &str {
+        "stl-binary"
+    }
+
+    fn identify(&self, input: &[u8]) -> Vec<Candidate> {
+        self.identify_prefix(input, input.len() as u64)
+    }
+
+    fn identify_prefix(&self, prefix: &[u8], length: u64) -> Vec<Candidate> {
+        match prefix.get(80..84).map(|count| u32::from_le_bytes(count.try_into().unwrap()) as u64) {
+            Some(count) if count > 0 && 84 + 50 * count == length => {
+                vec![Candidate {form: Form::Model3D, confidence: 0.6, mime: Some(MimeType::new("model", "stl")), identifier: self.name().into()}]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+// Formats that are ZIP archives underneath, told apart by the files they contain.
+pub struct ZipContainer;
+
+impl Identifier for ZipContainer {
+    fn name(&self) -> &str {
+        "zip-container"
+    }
+
+    fn identify(&self, input: &[u8]) -> Vec<Candidate> {
+        if magic_zip(input).is_err() {
+            return vec![];
+        }
+        let candidate = |form, confidence, kind, subtype| Candidate {form, confidence, mime: Some(MimeType::new(kind, subtype)), identifier: self.name().into()};
+        // An EPUB starts with an uncompressed "mimetype" file naming the format.
+        if input.get(30..58) == Some(b"mimetypeapplication/epub+zip".as_slice()) {
+            vec![candidate(Form::TypesetText, 0.97, "application", "epub+zip")]
+        } else if contains(input, b"word/document.xml") {
+            vec![candidate(Form::TypesetText, 0.95, "application", "vnd.openxmlformats-officedocument.wordprocessingml.document")]
+        } else if contains(input, b"AndroidManifest.xml") {
+            vec![candidate(Form::App, 0.95, "application", "vnd.android.package-archive")]
+        } else if contains(input, b"META-INF/MANIFEST.MF") {
+            vec![candidate(Form::App, 0.7, "application", "java-archive")]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// The identifiers to ask about some data. Identifiers can be registered in addition to the
+// built in ones, which `Registry::default()` starts out with.
+pub struct Registry {
+    identifiers: Vec<Box<dyn Identifier>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {identifiers: vec![]}
+    }
+
+    pub fn register(&mut self, identifier: impl Identifier + 'static) {
+        self.identifiers.push(Box::new(identifier));
+    }
+
+    // Every candidate of every identifier, most confident first.
+    pub fn identify(&self, input: &[u8]) -> Vec<Candidate> {
+        self.identify_prefix(input, input.len() as u64)
+    }
+
+    // Like `identify`, given only the first bytes of data that is `length` long. A character cut
+    // in two at the end of the prefix is left out, so that text still looks like text.
+    pub fn identify_prefix(&self, mut prefix: &[u8], length: u64) -> Vec<Candidate> {
+        if (prefix.len() as u64) < length {
+            if let Err(e) = std::str::from_utf8(prefix) {
+                if e.error_len().is_none() {
+                    prefix = &prefix[..e.valid_up_to()];
+                }
+            }
+        }
+        let mut candidates = self.identifiers.iter().flat_map(|identifier| identifier.identify_prefix(prefix, length)).collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        candidates
+    }
+
+    // The most confident candidate, or binary data if no identifier knows the data.
+    pub fn best(&self, input: &[u8]) -> Candidate {
+        self.best_prefix(input, input.len() as u64)
+    }
+
+    pub fn best_prefix(&self, prefix: &[u8], length: u64) -> Candidate {
+        self.identify_prefix(prefix, length).into_iter().next().unwrap_or(Candidate {
+            form: Form::Binary,
+            confidence: 0.0,
+            mime: Some(MimeType::new("application", "octet-stream")),
+            identifier: "none".into(),
+        })
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(Magic::new("tiff", magic_tiff, 0.9, "image", "tiff"));
+        registry.register(Magic::new("png", magic_png, 0.95, "image", "png"));
+        registry.register(Magic::new("jpeg", magic_jpeg, 0.9, "image", "jpeg"));
+        registry.register(Magic::new("gif", magic_gif, 0.95, "image", "gif"));
+        registry.register(Magic::new("webp", magic_webp, 0.95, "image", "webp"));
+        registry.register(Magic::new("bmp", magic_bmp, 0.8, "image", "bmp"));
+        registry.register(Magic::new("heif", magic_heif, 0.9, "image", "heif"));
+        registry.register(Magic::new("avif", magic_avif, 0.9, "image", "avif"));
+        registry.register(Magic::new("wav", magic_wav, 0.95, "audio", "wav"));
+        registry.register(Magic::new("flac", magic_flac, 0.95, "audio", "flac"));
+        registry.register(Magic::new("ogg", magic_ogg, 0.9, "audio", "ogg"));
+        registry.register(Magic::new("mp3", magic_mp3, 0.7, "audio", "mpeg"));
+        registry.register(Magic::new("m4a", magic_m4a, 0.9, "audio", "mp4"));
+        registry.register(Magic::new("mp4", magic_mp4, 0.7, "video", "mp4"));
+        registry.register(Magic::new("mkv", magic_mkv, 0.9, "video", "x-matroska"));
+        registry.register(Magic::new("avi", magic_avi, 0.95, "video", "x-msvideo"));
+        registry.register(Magic::new("zip", magic_zip, 0.9, "application", "zip"));
+        registry.register(Magic::new("gzip", magic_gzip, 0.9, "application", "gzip"));
+        registry.register(Magic::new("7z", magic_7z, 0.95, "application", "x-7z-compressed"));
+        registry.register(Magic::new("tar", magic_tar, 0.9, "application", "x-tar"));
+        registry.register(Magic::new("glb", magic_glb, 0.95, "model", "gltf-binary"));
+        registry.register(Magic::new("ply", magic_ply, 0.95, "model", "x-ply"));
+        registry.register(StlBinary);
+        registry.register(Magic::new("stl-ascii", magic_stl_ascii, 0.8, "model", "stl"));
+        registry.register(Magic::new("gltf", magic_gltf, 0.85, "model", "gltf+json"));
+        registry.register(Magic::new("obj", magic_obj, 0.7, "model", "obj"));
+        registry.register(Magic::new("elf", magic_elf, 0.95, "application", "x-elf"));
+        registry.register(Magic::new("pe", magic_pe, 0.95, "application", "vnd.microsoft.portable-executable"));
+        registry.register(Magic::new("mach-o", magic_macho, 0.85, "application", "x-mach-binary"));
+        registry.register(Magic::new("wasm", magic_wasm, 0.95, "application", "wasm"));
+        registry.register(Magic::new("pdf", magic_pdf, 0.95, "application", "pdf"));
+        registry.register(Magic::new("postscript", magic_postscript, 0.9, "application", "postscript"));
+        registry.register(Magic::new("dvi", magic_dvi, 0.9, "application", "x-dvi"));
+        registry.register(Magic::new("plaintext", magic_plaintext, 0.5, "text", "plain"));
+        registry.register(Json);
+        registry.register(ZipContainer);
+        registry
     }
 }
 
 pub fn magic_photo(input: &[u8]) -> IResult<&[u8], Form> {
-    magic_tiff(input)
+    alt((magic_tiff, magic_png, magic_jpeg, magic_gif, magic_webp, magic_bmp, magic_heif, magic_avif))(input)
 }
 
 pub fn magic_tiff(input: &[u8]) -> IResult<&[u8], Form> {
@@ -23,6 +233,317 @@ pub fn magic_tiff(input: &[u8]) -> IResult<&[u8], Form> {
     Ok((input, Form::Photo))
 }
 
+pub fn magic_png(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\x89PNG\r\n\x1A\n")(input)?;
+    Ok((input, Form::Photo))
+}
+
+pub fn magic_jpeg(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\xFF\xD8\xFF")(input)?;
+    Ok((input, Form::Photo))
+}
+
+pub fn magic_gif(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = alt((tag(b"GIF87a"), tag(b"GIF89a")))(input)?;
+    Ok((input, Form::Photo))
+}
+
+pub fn magic_webp(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"RIFF")(input)?;
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"WEBP")(input)?;
+    Ok((input, Form::Photo))
+}
+
+// "BM" alone is too likely to start a text, so also check the reserved bytes and the size of the
+// header that follows the file header.
+pub fn magic_bmp(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"BM")(input)?;
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"\x00\x00\x00\x00")(input)?;
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = verify(le_u32, |size| [12, 40, 52, 56, 64, 108, 124].contains(size))(input)?;
+    Ok((input, Form::Photo))
+}
+
+// HEIF and AVIF are ISO base media files whose ftyp box names an image brand.
+pub fn magic_heif(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"ftyp")(input)?;
+    let (input, _) = alt((tag(b"heic"), tag(b"heix"), tag(b"hevc"), tag(b"hevx"), tag(b"heim"), tag(b"heis"), tag(b"mif1"), tag(b"msf1")))(input)?;
+    Ok((input, Form::Photo))
+}
+
+pub fn magic_avif(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"ftyp")(input)?;
+    let (input, _) = alt((tag(b"avif"), tag(b"avis")))(input)?;
+    Ok((input, Form::Photo))
+}
+
+pub fn magic_sound(input: &[u8]) -> IResult<&[u8], Form> {
+    alt((magic_wav, magic_flac, magic_ogg, magic_mp3, magic_m4a))(input)
+}
+
+pub fn magic_wav(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"RIFF")(input)?;
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"WAVE")(input)?;
+    Ok((input, Form::Sound))
+}
+
+pub fn magic_flac(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"fLaC")(input)?;
+    Ok((input, Form::Sound))
+}
+
+pub fn magic_ogg(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"OggS")(input)?;
+    Ok((input, Form::Sound))
+}
+
+// Either an ID3 tag or two MPEG audio frames in a row. A lone frame header is too likely by
+// chance, e.g. UTF-16 text starts with FF FE.
+pub fn magic_mp3(input: &[u8]) -> IResult<&[u8], Form> {
+    if let Ok((input, _)) = tag::<_, _, nom::error::Error<&[u8]>>(b"ID3")(input) {
+        return Ok((input, Form::Sound));
+    }
+    match mpeg_frame_length(input) {
+        Some(length) if input.get(length..).and_then(mpeg_frame_length).is_some() => Ok((&input[length..], Form::Sound)),
+        _ => fail(input),
+    }
+}
+
+// The length of the MPEG audio frame whose header starts the input, if it is a valid header.
+// Free format (bitrate index 0) is rejected too, since its frames can't be measured.
+fn mpeg_frame_length(input: &[u8]) -> Option<usize> {
+    const BITRATES: [[u32; 14]; 5] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // MPEG 1, layer I
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384], // MPEG 1, layer II
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320], // MPEG 1, layer III
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256], // MPEG 2 and 2.5, layer I
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160], // MPEG 2 and 2.5, layers II and III
+    ];
+    let header = input.get(..4)?;
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 3; // 0 for MPEG 2.5, 2 for MPEG 2, 3 for MPEG 1.
+    let layer = 4 - ((header[1] >> 1) & 3); // 4 is reserved.
+    let bitrate = (header[2] >> 4) as usize;
+    let sample_rate = (header[2] >> 2) & 3;
+    let padding = ((header[2] >> 1) & 1) as u32;
+    if version == 1 || layer == 4 || bitrate == 0 || bitrate == 15 || sample_rate == 3 {
+        return None;
+    }
+    let table = match (version, layer) {
+        (3, layer) => layer as usize - 1,
+        (_, 1) => 3,
+        _ => 4,
+    };
+    let bitrate = BITRATES[table][bitrate - 1] * 1000;
+    let sample_rate = [44100, 48000, 32000][sample_rate as usize] >> (3 - version.max(1)); // Halved for MPEG 2, quartered for 2.5.
+    let length = match (version, layer) {
+        (_, 1) => (12 * bitrate / sample_rate + padding) * 4,
+        (3, _) | (_, 2) => 144 * bitrate / sample_rate + padding,
+        _ => 72 * bitrate / sample_rate + padding,
+    };
+    Some(length as usize)
+}
+
+pub fn magic_m4a(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"ftyp")(input)?;
+    let (input, _) = alt((tag(b"M4A "), tag(b"M4B ")))(input)?;
+    Ok((input, Form::Sound))
+}
+
+pub fn magic_video(input: &[u8]) -> IResult<&[u8], Form> {
+    alt((magic_mp4, magic_mkv, magic_avi))(input)
+}
+
+// Any ISO base media file that isn't an image or audio is taken to be a video.
+pub fn magic_mp4(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"ftyp")(input)?;
+    Ok((input, Form::Video))
+}
+
+pub fn magic_mkv(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\x1A\x45\xDF\xA3")(input)?;
+    Ok((input, Form::Video))
+}
+
+pub fn magic_avi(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"RIFF")(input)?;
+    let (input, _) = take(4usize)(input)?;
+    let (input, _) = tag(b"AVI ")(input)?;
+    Ok((input, Form::Video))
+}
+
+pub fn magic_archive(input: &[u8]) -> IResult<&[u8], Form> {
+    alt((magic_zip, magic_gzip, magic_7z, magic_tar))(input)
+}
+
+pub fn magic_zip(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = alt((tag(b"PK\x03\x04"), tag(b"PK\x05\x06")))(input)?;
+    Ok((input, Form::Archive))
+}
+
+pub fn magic_gzip(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\x1F\x8B")(input)?;
+    Ok((input, Form::Archive))
+}
+
+pub fn magic_7z(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"7z\xBC\xAF\x27\x1C")(input)?;
+    Ok((input, Form::Archive))
+}
+
+pub fn magic_tar(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = take(257usize)(input)?;
+    let (input, _) = tag(b"ustar")(input)?;
+    Ok((input, Form::Archive))
+}
+
+// Binary STLs are identified by their length, see StlBinary.
+pub fn magic_model3d(input: &[u8]) -> IResult<&[u8], Form> {
+    if !StlBinary.identify(input).is_empty() {
+        return Ok((&[], Form::Model3D));
+    }
+    alt((magic_glb, magic_ply, magic_stl_ascii, magic_gltf, magic_obj))(input)
+}
+
+pub fn magic_glb(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"glTF")(input)?;
+    Ok((input, Form::Model3D))
+}
+
+pub fn magic_ply(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = alt((tag(b"ply\n"), tag(b"ply\r\n")))(input)?;
+    let (input, _) = tag(b"format ")(input)?;
+    Ok((input, Form::Model3D))
+}
+
+pub fn magic_stl_ascii(input: &[u8]) -> IResult<&[u8], Form> {
+    let (rest, _) = tag(b"solid")(input)?;
+    if !contains(rest, b"facet normal") {
+        return fail(input);
+    }
+    Ok((&[], Form::Model3D))
+}
+
+// glTF JSON is told apart from other JSON by its required "asset" property, an object with a
+// string "version". Only the properties up to it are read, so that the start of a large file
+// still counts; exporters write it first.
+pub fn magic_gltf(input: &[u8]) -> IResult<&[u8], Form> {
+    let found = Cell::new(false);
+    let _ = serde_json::Deserializer::from_slice(input).deserialize_map(GltfAsset(&found));
+    if found.get() { Ok((&[], Form::Model3D)) } else { fail(input) }
+}
+
+// Visits the top-level properties of a JSON object until it finds a glTF asset. What follows may
+// be cut off, so whether it was found is noted aside rather than returned.
+struct GltfAsset<'a>(&'a Cell<bool>);
+
+impl<'de> Visitor<'de> for GltfAsset<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a glTF object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        #[derive(Deserialize)]
+        struct Asset {
+            #[allow(dead_code)]
+            version: String,
+        }
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "asset" {
+                map.next_value::<Asset>()?;
+                self.0.set(true);
+                return Ok(());
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+}
+
+// Wavefront OBJ is plain text where every line is a comment or starts with an OBJ statement.
+pub fn magic_obj(input: &[u8]) -> IResult<&[u8], Form> {
+    const STATEMENTS: [&str; 15] = ["v", "vt", "vn", "vp", "f", "l", "p", "o", "g", "s", "mtllib", "usemtl", "cstype", "deg", "curv"];
+    let s = match std::str::from_utf8(input) {
+        Ok(s) => s,
+        Err(_) => return fail(input),
+    };
+    let mut vertices = false;
+    for line in s.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let statement = line.split_whitespace().next().unwrap_or("");
+        if !STATEMENTS.contains(&statement) {
+            return fail(input);
+        }
+        vertices |= statement == "v";
+    }
+    if vertices {
+        Ok((&[], Form::Model3D))
+    } else {
+        fail(input)
+    }
+}
+
+pub fn magic_app(input: &[u8]) -> IResult<&[u8], Form> {
+    alt((magic_elf, magic_pe, magic_macho, magic_wasm))(input)
+}
+
+pub fn magic_elf(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\x7FELF")(input)?;
+    Ok((input, Form::App))
+}
+
+// "MZ" starts every DOS executable; a PE file also has a "PE\0\0" signature where the DOS header
+// says its header is.
+pub fn magic_pe(input: &[u8]) -> IResult<&[u8], Form> {
+    let start = input;
+    let (input, _) = tag(b"MZ")(input)?;
+    let (input, _) = take(0x3Ausize)(input)?;
+    let (_, offset) = le_u32(input)?;
+    let (input, _) = take(offset as usize)(start)?;
+    let (input, _) = tag(b"PE\x00\x00")(input)?;
+    Ok((input, Form::App))
+}
+
+pub fn magic_macho(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = alt((tag(b"\xFE\xED\xFA\xCE"), tag(b"\xFE\xED\xFA\xCF"), tag(b"\xCE\xFA\xED\xFE"), tag(b"\xCF\xFA\xED\xFE"), tag(b"\xCA\xFE\xBA\xBE")))(input)?;
+    Ok((input, Form::App))
+}
+
+pub fn magic_wasm(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\x00asm")(input)?;
+    Ok((input, Form::App))
+}
+
+pub fn magic_typeset(input: &[u8]) -> IResult<&[u8], Form> {
+    alt((magic_pdf, magic_postscript, magic_dvi))(input)
+}
+
+pub fn magic_pdf(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"%PDF-")(input)?;
+    Ok((input, Form::TypesetText))
+}
+
+pub fn magic_postscript(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"%!PS")(input)?;
+    Ok((input, Form::TypesetText))
+}
+
+// The preamble opcode followed by the DVI format identifier.
+pub fn magic_dvi(input: &[u8]) -> IResult<&[u8], Form> {
+    let (input, _) = tag(b"\xF7\x02")(input)?;
+    Ok((input, Form::TypesetText))
+}
+
 pub fn magic_plaintext(input: &[u8]) -> IResult<&[u8], Form> {
     let result = String::from_utf8(input.to_vec());
     if result.is_ok() {
@@ -30,4 +551,68 @@ pub fn magic_plaintext(input: &[u8]) -> IResult<&[u8], Form> {
     } else {
         fail(&[])
     }
-}
\ No newline at end of file
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two frames of MPEG 1 layer III at 128 kbit/s and 44.1 kHz, 417 bytes each.
+    fn mp3() -> Vec<u8> {
+        let mut frame = b"\xFF\xFB\x90\x00".to_vec();
+        frame.resize(417, 0);
+        frame.repeat(2)
+    }
+
+    #[test]
+    fn mp3_needs_two_frames() {
+        assert!(magic_mp3(mp3().as_slice()).is_ok());
+        assert!(magic_mp3(&mp3()[..417]).is_err());
+        assert!(magic_mp3(b"ID3\x04\x00").is_ok());
+    }
+
+    #[test]
+    fn mp3_rejects_reserved_fields() {
+        for header in [b"\xFF\xEB\x90\x00", b"\xFF\xF9\x90\x00", b"\xFF\xFB\x00\x00", b"\xFF\xFB\xF0\x00", b"\xFF\xFB\x9C\x00"] {
+            let mut data = mp3();
+            data[..4].copy_from_slice(header);
+            data[417..421].copy_from_slice(header);
+            assert!(magic_mp3(data.as_slice()).is_err(), "{header:x?}");
+        }
+    }
+
+    #[test]
+    fn stl_binary_needs_a_matching_count() {
+        let mut stl = vec![0; 84 + 50 * 2];
+        stl[80] = 2;
+        assert!(magic_model3d(&stl).is_ok());
+        assert!(Registry::default().best(&stl).form == Form::Model3D);
+        assert!(magic_model3d(&stl[..stl.len() - 1]).is_err());
+        stl[80] = 0;
+        assert!(magic_model3d(&stl).is_err());
+    }
+
+    #[test]
+    fn gltf_needs_an_asset_with_a_version() {
+        let registry = Registry::default();
+        let gltf = br#"{"asset": {"generator": "test", "version": "2.0"}, "scenes": [{"nodes": [0]}]}"#;
+        assert!(registry.best(gltf).identifier == "gltf");
+        // Only the start of a large file is looked at.
+        assert!(registry.best_prefix(&gltf[..60], 1 << 20).identifier == "gltf");
+        for json in [r#"{"asset": 1, "version": 2}"#, r#"{"asset": {"version": 2}}"#, r#"{"nested": {"asset": {"version": "2.0"}}}"#, r#"[{"asset": {"version": "2.0"}}]"#] {
+            assert!(magic_gltf(json.as_bytes()).is_err(), "{json}");
+            assert!(registry.best(json.as_bytes()).identifier == "json", "{json}");
+        }
+    }
+
+    #[test]
+    fn utf16_text_is_not_mp3() {
+        let text = "\u{FEFF}hello, this is text".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        assert!(magic_mp3(text.as_slice()).is_err());
+        assert!(Registry::default().best(text.as_slice()).form != Form::Sound);
+    }
+}
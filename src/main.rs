@@ -1,15 +1,19 @@
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::ops::Mul;
-use crate::object::{Form, Object};
-use crate::object::builders::*;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use object_system_prototyping::export::{export_object, export_objects, file_name};
+use object_system_prototyping::import::{insert_imported, Imported, SymlinkPolicy};
+use object_system_prototyping::library::Library;
+use object_system_prototyping::object::{Form, MergePolicy, ObjectId};
+use object_system_prototyping::query::{parse_tag, Query, Term};
+use object_system_prototyping::similar::{near_duplicates, THRESHOLD};
+use object_system_prototyping::thumbnail::size_for;
+use object_system_prototyping::worker::{Event, Job, Workers};
 use eframe::egui;
 use eframe::egui::Vec2;
-use crate::magic_identify::magic_identify;
-
-mod object;
-mod magic_identify;
+use egui_extras::RetainedImage;
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
@@ -20,22 +24,80 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Object Viewer",
         options,
-        Box::new(|_cc| {
-            Box::<MyApp>::default()
+        Box::new(|cc| {
+            Box::new(MyApp::new(&cc.egui_ctx))
         }),
     )
 }
 
+const STORE: &str = "object_store";
+
+// How much of an object's data is read for its preview in the list, and at most for the detail view.
+const PREVIEW_BYTES: usize = 1024;
+const DETAIL_BYTES: usize = 64 * 1024;
+
+// The kinds of tags users can add in the detail view, and their names.
+const TAG_KINDS: [(&str, &str); 6] = [
+    ("title", "Title"),
+    ("author", "Author"),
+    ("category", "Category"),
+    ("location", "Location"),
+    ("date.created", "Date created"),
+    ("date.edited", "Date edited"),
+];
+
+// What happens to the tags of data that is already stored, and how that is put to users.
+const MERGE_POLICIES: [(MergePolicy, &str); 3] = [
+    (MergePolicy::Union, "Merge the tags"),
+    (MergePolicy::KeepExisting, "Keep the stored tags"),
+    (MergePolicy::KeepIncoming, "Keep the new tags"),
+];
+
+// How far along the import of a file is.
+enum ImportStatus {
+    Queued,
+    Reading,
+    Done,
+    Duplicate(String), // Already stored. Says which of its tags were kept and which dropped.
+    Failed(String),
+    Cancelled,
+}
+
 struct MyApp {
     query: String,
-    objects: HashSet<Object>,
-    imgs: Vec<(egui_extras::RetainedImage, Object)>,
-    ptxts: Vec<(String, Object)>,
-    bins: Vec<(String, Object)>,
-    picked: Option<usize>,
+    query_error: Option<String>,
+    library: Library,
+    workers: Workers,
+    imports: Vec<(PathBuf, ImportStatus)>,
+    import_index: HashMap<PathBuf, usize>, // Where each file is in `imports`.
+    show_imports: bool,
+    merge_policy: MergePolicy, // For imports and for merging similar photos.
+    show_similar: bool,
+    hashes: HashMap<ObjectId, Result<u64, String>>, // Perceptual hashes of photos, see similar.rs.
+    hashing: HashSet<ObjectId>,
+    threshold: u32,
+    similar: Option<Vec<Vec<ObjectId>>>, // Groups of photos that look alike, made again when None.
+    not_similar: HashSet<Vec<ObjectId>>, // Groups that were dismissed.
+    imgs: Vec<ObjectId>,
+    thumbnails: HashMap<(ObjectId, u32), Result<RetainedImage, String>>, // Why there is no preview if the photo can't be decoded.
+    loading_thumbnails: HashSet<(ObjectId, u32)>,
+    full_image: Option<(ObjectId, Result<RetainedImage, String>)>, // The picked photo at full size.
+    decoding: Option<ObjectId>,
+    ptxts: Vec<ObjectId>,
+    bins: Vec<ObjectId>,
+    previews: HashMap<ObjectId, String>, // The start of each plain text or binary, read when it is first shown.
+    detail: Option<(ObjectId, String)>, // The picked plain text or binary.
+    picked: Option<ObjectId>,
     picktype: Form,
+    new_tag_kind: &'static str,
+    new_tag_value: String,
+    tag_error: Option<String>,
+    errors: Vec<String>, // Shown in a panel at the bottom until dismissed.
+    scratch: Option<PathBuf>, // Set if the store couldn't be loaded. Changes then go to this temporary store instead.
     allowed_to_close: bool,
     show_confirmation_dialog: bool,
+    save_failed: bool, // Whether saving before quitting failed.
+    unsaved_imports: bool, // Whether files were imported since the library was last saved.
     dropped_files: Vec<egui::DroppedFile>,
     ask_to_delete: bool,
     starting_up: bool,
@@ -46,19 +108,58 @@ struct MyApp {
     show_show: bool,
 }
 
-impl Default for MyApp {
-    fn default() -> Self {
-        let objects = load_objects("object_store");
+impl MyApp {
+    fn new(ctx: &egui::Context) -> Self {
+        let (library, scratch, errors) = match Library::load(STORE) {
+            Ok(library) => {
+                let errors = library.recovered_from().map(|backup| format!("The store was damaged and has been restored from {}", backup.display()));
+                (library, None, errors.into_iter().collect())
+            }
+            Err(e) => {
+                let scratch = env::temp_dir().join(format!("{STORE}-{}", std::process::id()));
+                let library = Library::load(&scratch.to_string_lossy()).expect("could not create a temporary store");
+                (library, Some(scratch), vec![format!("Could not load the store, changes will not be saved: {e}")])
+            }
+        };
+        let ctx = ctx.clone();
+        let threads = thread::available_parallelism().map_or(2, |n| n.get());
+        let store = scratch.clone().unwrap_or(PathBuf::from(STORE));
+        let workers = Workers::new(threads, &store, library.identifiers().clone(), move || ctx.request_repaint());
         Self {
             query: String::new(),
-            objects,
+            query_error: None,
+            library,
+            workers,
+            imports: vec![],
+            import_index: HashMap::new(),
+            show_imports: false,
+            merge_policy: MergePolicy::Union,
+            show_similar: false,
+            hashes: HashMap::new(),
+            hashing: HashSet::new(),
+            threshold: THRESHOLD,
+            similar: None,
+            not_similar: HashSet::new(),
             imgs: vec![],
+            thumbnails: HashMap::new(),
+            loading_thumbnails: HashSet::new(),
+            full_image: None,
+            decoding: None,
             ptxts: vec![],
             bins: vec![],
+            previews: HashMap::new(),
+            detail: None,
             picked: None,
             picktype: Form::Empty,
+            new_tag_kind: TAG_KINDS[0].0,
+            new_tag_value: String::new(),
+            tag_error: None,
+            errors,
+            scratch,
             allowed_to_close: false,
             show_confirmation_dialog: false,
+            save_failed: false,
+            unsaved_imports: false,
             dropped_files: vec![],
             ask_to_delete: false,
             starting_up: true,
@@ -72,38 +173,364 @@ impl Default for MyApp {
 }
 
 impl MyApp {
-    fn refresh_images(&mut self) {
+    fn parse_query(&mut self) -> Query {
+        match Query::parse(&self.query) {
+            Ok(query) => {
+                self.query_error = None;
+                query
+            }
+            Err(e) => {
+                self.query_error = Some(e);
+                Query::Term(Term::Text(self.query.clone()))
+            }
+        }
+    }
+
+    fn refresh_images(&mut self, ids: &HashSet<ObjectId>) {
         self.imgs.clear();
         self.picked = None;
         self.picktype = Form::Empty;
-        for object in self.objects.iter().filter(|o| o.form == Form::Photo).filter(|o| o.search(self.query.clone())) {
-            let img = egui_extras::RetainedImage::from_image_bytes("img",&object.data).unwrap();
-            self.imgs.push((img, object.clone()));
+        self.full_image = None;
+        self.decoding = None;
+        // Thumbnails are only loaded once they are scrolled into view, see thumbnail_ui.
+        self.imgs.extend(ids.iter().filter_map(|id| self.library.get(id)).filter(|o| o.form == Form::Photo).map(|o| o.id));
+        self.thumbnails.retain(|(id, _), _| ids.contains(id));
+    }
+
+    // Draws the thumbnail of a photo in a square of the chosen size. It is asked of the workers
+    // when the square first comes into view. Photos that can't be decoded, like HEIF for now,
+    // get no preview.
+    fn thumbnail_ui(&mut self, ui: &mut egui::Ui, id: ObjectId) {
+        let size = self.size as f32;
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(size), egui::Sense::hover());
+        if !ui.is_rect_visible(rect) {
+            return;
+        }
+        let key = (id, size_for(self.size as u32));
+        if !self.thumbnails.contains_key(&key) && self.loading_thumbnails.insert(key) {
+            if let Some(object) = self.library.get(&id) {
+                self.workers.submit(Job::Thumbnail {object: object.clone(), size: key.1});
+            }
+        }
+        match self.thumbnails.get(&key) {
+            None => {
+                ui.put(rect, egui::Spinner::new());
+            }
+            Some(Ok(image)) => {
+                let fitted = image.size_vec2() * (size / image.size_vec2().max_elem()).min(1.0);
+                let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                ui.painter().image(image.texture_id(ui.ctx()), egui::Rect::from_center_size(rect.center(), fitted), uv, egui::Color32::WHITE);
+            }
+            Some(Err(e)) => {
+                ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, "No preview", egui::TextStyle::Body.resolve(ui.style()), ui.visuals().weak_text_color());
+                ui.interact(rect, ui.id().with(id), egui::Sense::hover()).on_hover_text(e.as_str());
+            }
         }
     }
 
-    fn refresh_plaintext(&mut self) {
+    // Texts and binaries are previewed from the start of their data, see preview_ui.
+    fn refresh_plaintext(&mut self, ids: &HashSet<ObjectId>) {
         self.ptxts.clear();
         self.picked = None;
         self.picktype = Form::Empty;
-        for object in self.objects.iter().filter(|o| o.form == Form::PlainText).filter(|o| o.search(self.query.clone())) {
-            self.ptxts.push((String::from_utf8_lossy(object.data.as_slice()).to_string(),object.clone()));
-        }
+        self.detail = None;
+        self.ptxts.extend(ids.iter().filter_map(|id| self.library.get(id)).filter(|o| o.form == Form::PlainText).map(|o| o.id));
+        self.previews.retain(|id, _| ids.contains(id));
     }
 
-    fn refresh_bins(&mut self) {
+    fn refresh_bins(&mut self, ids: &HashSet<ObjectId>) {
         self.bins.clear();
         self.picked = None;
         self.picktype = Form::Empty;
-        for object in self.objects.iter().filter(|o| o.form == Form::Binary).filter(|o| o.search(self.query.clone())) {
-            self.bins.push((hex::encode(&object.data),object.clone()));
+        self.detail = None;
+        // Everything that can't be shown as a photo or text is shown as hex.
+        self.bins.extend(ids.iter().filter_map(|id| self.library.get(id)).filter(|o| !matches!(o.form, Form::Photo | Form::PlainText | Form::Empty)).map(|o| o.id));
+    }
+
+    // Shows the start of a plain text or binary in the list, read once and kept while it is listed.
+    fn preview_ui(&mut self, ui: &mut egui::Ui, id: ObjectId) {
+        let library = &self.library;
+        let preview = self.previews.entry(id).or_insert_with(|| read_text(library, &id, PREVIEW_BYTES));
+        ui.label(truncate_dotted(preview.clone(), self.size));
+    }
+
+    // Exports the picked object to a file, or everything that is shown to a folder.
+    fn export(&mut self) {
+        let result = match self.picked.and_then(|id| self.library.get(&id)) {
+            Some(object) => match rfd::FileDialog::new().set_file_name(file_name(object)).save_file() {
+                Some(path) => export_object(object, &path),
+                None => Ok(()),
+            },
+            None => match rfd::FileDialog::new().pick_folder() {
+                Some(dir) => {
+                    let mut ids = vec![];
+                    if self.show_imgs {
+                        ids.extend(self.imgs.iter().cloned());
+                    }
+                    if self.show_ptxts {
+                        ids.extend(self.ptxts.iter().cloned());
+                    }
+                    if self.show_bins {
+                        ids.extend(self.bins.iter().cloned());
+                    }
+                    export_objects(ids.iter().filter_map(|id| self.library.get(id)), &dir).map(|_| ())
+                }
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            self.errors.push(format!("Could not export: {e}"));
+        }
+    }
+
+    // Returns whether the library was saved, or didn't have to be.
+    fn save(&mut self) -> bool {
+        if self.scratch.is_some() {
+            return true;
+        }
+        match self.library.save() {
+            Ok(()) => true,
+            Err(e) => {
+                self.errors.push(format!("Could not save: {e}"));
+                false
+            }
+        }
+    }
+
+    // Lists the tags of an object with buttons to remove them, and a row to add a tag. A tag
+    // that conflicts with an existing one, like a second title, replaces it.
+    fn tag_editor(&mut self, ui: &mut egui::Ui, id: ObjectId) {
+        let mut tags = self.library.get(&id).map(|object| object.tags.iter().cloned().collect::<Vec<_>>()).unwrap_or_default();
+        tags.sort_by_key(|tag| tag.to_string());
+        let mut changed = false;
+        for tag in tags {
+            ui.horizontal(|ui| {
+                if ui.small_button("🗑").clicked() {
+                    changed |= self.library.update(&id, |object| {
+                        object.remove_tag(&tag);
+                    });
+                }
+                ui.label(tag.to_string());
+            });
+        }
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("new_tag_kind")
+                .selected_text(TAG_KINDS.iter().find(|(kind, _)| *kind == self.new_tag_kind).map_or("", |(_, name)| *name))
+                .show_ui(ui, |ui| {
+                    for (kind, name) in TAG_KINDS {
+                        ui.selectable_value(&mut self.new_tag_kind, kind, name);
+                    }
+                });
+            ui.text_edit_singleline(&mut self.new_tag_value);
+            if ui.button("Add").clicked() {
+                let mut result = parse_tag(self.new_tag_kind, self.new_tag_value.trim());
+                if let Ok(tag) = result.clone() {
+                    self.library.update(&id, |object| {
+                        result = match object.conflicting_tag(&tag).cloned() {
+                            Some(old) => object.replace_tag(&old, tag.clone()),
+                            None => object.add_tag(tag.clone()),
+                        }.map(|_| tag);
+                    });
+                }
+                match result {
+                    Ok(_) => {
+                        self.new_tag_value.clear();
+                        self.tag_error = None;
+                        changed = true;
+                    }
+                    Err(e) => self.tag_error = Some(e),
+                }
+            }
+            if let Some(e) = &self.tag_error {
+                ui.colored_label(ui.visuals().error_fg_color, "⚠").on_hover_text(e);
+            }
+        });
+        if changed {
+            self.save();
+        }
+    }
+
+    // Takes in what the workers have done since the last frame.
+    fn handle_events(&mut self) {
+        let mut imported = false;
+        for event in self.workers.events().collect::<Vec<_>>() {
+            match event {
+                Event::Queued(path) => self.set_import_status(path, ImportStatus::Queued),
+                Event::Started(path) => self.set_import_status(path, ImportStatus::Reading),
+                Event::Imported(path, received) => match insert_imported(*received, &mut self.library, self.merge_policy) {
+                    Ok(Imported::New(_)) => {
+                        self.set_import_status(path, ImportStatus::Done);
+                        imported = true;
+                    }
+                    Ok(Imported::Duplicate {kept, dropped, ..}) => {
+                        let mut report = String::from("Already stored.");
+                        for tag in kept {
+                            report += &format!("\n+ {tag}");
+                        }
+                        for tag in dropped {
+                            report += &format!("\n- {tag}");
+                        }
+                        self.set_import_status(path, ImportStatus::Duplicate(report));
+                        imported = true;
+                    }
+                    Err(e) => {
+                        self.errors.push(format!("Could not import {}: {e}", path.display()));
+                        self.set_import_status(path, ImportStatus::Failed(e.to_string()));
+                    }
+                },
+                Event::Failed(path, e) => {
+                    self.errors.push(format!("Could not import {}: {e}", path.display()));
+                    self.set_import_status(path, ImportStatus::Failed(e.to_string()));
+                }
+                Event::Cancelled(path) => self.set_import_status(path, ImportStatus::Cancelled),
+                Event::Thumbnail(id, size, image) => {
+                    self.loading_thumbnails.remove(&(id, size));
+                    self.thumbnails.insert((id, size), image.map(|image| retained(id, image)).map_err(|e| e.to_string()));
+                }
+                Event::Decoded(id, image) => {
+                    if self.decoding == Some(id) {
+                        self.full_image = Some((id, image.map(|image| retained(id, image)).map_err(|e| e.to_string())));
+                    }
+                }
+                Event::Hashed(id, hash) => {
+                    self.hashing.remove(&id);
+                    self.hashes.insert(id, hash.map_err(|e| e.to_string()));
+                    self.similar = None;
+                }
+            }
+        }
+        // The detail view stays open; the results are refreshed when going back.
+        if imported && self.picked.is_none() {
+            self.refresh();
+        }
+        // Saved once the last file of a batch is in, so that a crash doesn't lose the batch.
+        self.unsaved_imports |= imported;
+        if self.unsaved_imports && !self.imports.iter().any(|(_, status)| matches!(status, ImportStatus::Queued | ImportStatus::Reading)) {
+            self.unsaved_imports = false;
+            self.save();
+        }
+    }
+
+    fn set_import_status(&mut self, path: PathBuf, status: ImportStatus) {
+        match self.import_index.get(&path) {
+            Some(i) => self.imports[*i].1 = status,
+            None => {
+                self.import_index.insert(path.clone(), self.imports.len());
+                self.imports.push((path, status));
+            }
+        }
+    }
+
+    // Lists the files being imported with how far along they are, and why they failed.
+    fn imports_ui(&mut self, ui: &mut egui::Ui) {
+        let finished = self.imports.iter().filter(|(_, status)| !matches!(status, ImportStatus::Queued | ImportStatus::Reading)).count();
+        ui.add(egui::ProgressBar::new(finished as f32 / self.imports.len().max(1) as f32).text(format!("{finished} of {} files", self.imports.len())));
+        ui.horizontal(|ui| {
+            ui.label("Files that are already stored:");
+            merge_policy_ui(ui, "import_merge_policy", &mut self.merge_policy);
+        });
+        ui.horizontal(|ui| {
+            if ui.add_enabled(finished < self.imports.len(), egui::Button::new("Cancel")).clicked() {
+                self.workers.cancel_imports();
+            }
+            if ui.add_enabled(finished > 0, egui::Button::new("Clear finished")).clicked() {
+                self.imports.retain(|(_, status)| matches!(status, ImportStatus::Queued | ImportStatus::Reading));
+                self.import_index = self.imports.iter().enumerate().map(|(i, (path, _))| (path.clone(), i)).collect();
+            }
+        });
+        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            for (path, status) in &self.imports {
+                ui.horizontal(|ui| {
+                    match status {
+                        ImportStatus::Queued => ui.weak("Queued"),
+                        ImportStatus::Reading => ui.label("Reading"),
+                        ImportStatus::Done => ui.label("Done"),
+                        ImportStatus::Duplicate(report) => ui.label("Duplicate").on_hover_text(report),
+                        ImportStatus::Failed(e) => ui.colored_label(ui.visuals().error_fg_color, "Failed").on_hover_text(e),
+                        ImportStatus::Cancelled => ui.weak("Cancelled"),
+                    };
+                    ui.label(path.display().to_string());
+                });
+            }
+        });
+    }
+
+    // Groups photos that look alike so that near duplicates can be merged into one. Photos are
+    // hashed by the workers the first time they are compared.
+    fn similar_ui(&mut self, ui: &mut egui::Ui) {
+        let mut photos = 0;
+        for object in self.library.objects().filter(|object| object.form == Form::Photo) {
+            photos += 1;
+            if !self.hashes.contains_key(&object.id) && self.hashing.insert(object.id) {
+                self.workers.submit(Job::Hash {object: object.clone()});
+            }
+        }
+        if !self.hashing.is_empty() {
+            let hashed = photos - self.hashing.len();
+            ui.add(egui::ProgressBar::new(hashed as f32 / photos.max(1) as f32).text(format!("Compared {hashed} of {photos} photos")));
+        }
+        ui.horizontal(|ui| {
+            if ui.add(egui::Slider::new(&mut self.threshold, 0..=20).text("Difference")).changed() {
+                self.similar = None;
+            }
+            ui.label("Tags of the others:");
+            merge_policy_ui(ui, "similar_merge_policy", &mut self.merge_policy);
+        });
+        let groups = self.similar.get_or_insert_with(|| {
+            let hashes = self.hashes.iter()
+                .filter(|(id, _)| self.library.get(id).is_some())
+                .filter_map(|(id, hash)| hash.as_ref().ok().map(|hash| (*id, *hash)))
+                .collect();
+            let mut groups = near_duplicates(&hashes, self.threshold);
+            groups.retain(|group| !self.not_similar.contains(group));
+            groups
+        }).clone();
+        if groups.is_empty() && self.hashing.is_empty() {
+            ui.label("No photos look alike.");
+        }
+        let (mut keep, mut dismiss) = (None, None);
+        egui::ScrollArea::vertical().max_height(500.0).show(ui, |ui| {
+            for group in &groups {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        for id in group {
+                            ui.vertical(|ui| {
+                                self.thumbnail_ui(ui, *id);
+                                let size = self.library.get(id).map_or(0, |object| object.data.size());
+                                ui.label(format!("{}\n{} KiB", describe(&self.library, id), size / 1024));
+                                if ui.button("Keep this").on_hover_text("Merge the tags of the others into this photo and delete them").clicked() {
+                                    keep = Some((*id, group.clone()));
+                                }
+                            });
+                        }
+                    });
+                    if ui.button("Not alike").clicked() {
+                        dismiss = Some(group.clone());
+                    }
+                });
+            }
+        });
+        if let Some((keep, group)) = keep {
+            for id in group.iter().filter(|id| **id != keep) {
+                self.library.merge_into(&keep, id, self.merge_policy);
+                self.hashes.remove(id);
+            }
+            self.similar = None;
+            self.refresh();
+            self.save();
+        }
+        if let Some(group) = dismiss {
+            self.not_similar.insert(group);
+            self.similar = None;
         }
     }
 
     fn refresh(&mut self) {
-        self.refresh_images();
-        self.refresh_plaintext();
-        self.refresh_bins();
+        let query = self.parse_query();
+        let ids = self.library.search(&query);
+        self.refresh_images(&ids);
+        self.refresh_plaintext(&ids);
+        self.refresh_bins(&ids);
     }
 }
 
@@ -114,6 +541,22 @@ impl eframe::App for MyApp {
             self.refresh();
             self.starting_up = false;
         }
+        self.handle_events();
+        if !self.errors.is_empty() {
+            egui::TopBottomPanel::bottom("errors").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, format!("⚠ {} errors", self.errors.len()));
+                    if ui.button("Dismiss").clicked() {
+                        self.errors.clear();
+                    }
+                });
+                egui::ScrollArea::vertical().max_height(120.0).show(ui, |ui| {
+                    for e in &self.errors {
+                        ui.label(e);
+                    }
+                });
+            });
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
                 if ui.button(if self.picked.is_none() {"Refresh"} else {"Back"}).clicked() {
@@ -122,11 +565,21 @@ impl eframe::App for MyApp {
                 if ui.text_edit_singleline(&mut self.query).changed() {
                     self.refresh();
                 }
+                if let Some(e) = &self.query_error {
+                    ui.colored_label(ui.visuals().error_fg_color, "⚠").on_hover_text(e);
+                }
                 if self.picked.is_none() {
                     ui.add(egui::Slider::new(&mut self.size, 32..=256).text("Size"));
                     ui.checkbox(&mut self.show_show, "Show/hide");
+                    ui.toggle_value(&mut self.show_similar, "Similar photos");
 
                 }
+                if !self.imports.is_empty() {
+                    ui.toggle_value(&mut self.show_imports, "Imports");
+                }
+                if ui.button("Export…").clicked() {
+                    self.export();
+                }
                 if self.picked.is_some() {
                     if ui.button("🗑").clicked() {
                         self.ask_to_delete = true;
@@ -136,9 +589,11 @@ impl eframe::App for MyApp {
 
             if !self.dropped_files.is_empty() {
                 for file in &self.dropped_files {
-                    import_file(file.path.clone().unwrap().as_path().to_str().unwrap(), &mut self.objects);
+                    if let Some(path) = file.path.clone() {
+                        self.workers.submit(Job::Import {path, symlinks: SymlinkPolicy::Skip});
+                    }
                 }
-                self.refresh();
+                self.show_imports = true;
                 self.dropped_files.clear();
             }
 
@@ -155,66 +610,76 @@ impl eframe::App for MyApp {
                 if let Some(picked) = self.picked {
                     match self.picktype {
                         Form::Photo => {
-                            ui.image(self.imgs[picked].0.texture_id(ui.ctx()),  self.imgs[picked].0.size_vec2());
-                            ui.label(&self.imgs[picked].1.tags.iter().map(|tag| format!("{}",tag)).collect::<Vec<_>>().join("\n"));
+                            if self.decoding != Some(picked) {
+                                self.decoding = Some(picked);
+                                self.full_image = None;
+                                if let Some(object) = self.library.get(&picked) {
+                                    self.workers.submit(Job::Decode {object: object.clone()});
+                                }
+                            }
+                            match &self.full_image {
+                                Some((_, Ok(img))) => {
+                                    ui.image(img.texture_id(ui.ctx()), img.size_vec2());
+                                }
+                                Some((_, Err(e))) => {
+                                    ui.label("No preview").on_hover_text(e.as_str());
+                                }
+                                None => {
+                                    ui.spinner();
+                                }
+                            }
                         }
                         Form::Empty => {
                             ui.label("--- Empty object ---");
                         },
-                        Form::PlainText => {
-                            ui.label(&self.ptxts[picked].0);
-                        },
-                        Form::Binary => {
-                            ui.label(&self.bins[picked].0);
+                        _ => {
+                            if self.detail.as_ref().map(|(id, _)| *id) != Some(picked) {
+                                self.detail = Some((picked, read_text(&self.library, &picked, DETAIL_BYTES)));
+                            }
+                            if let Some((_, text)) = &self.detail {
+                                ui.label(text);
+                            }
                         }
-                        _ => {}
                     }
+                    ui.separator();
+                    self.tag_editor(ui, picked);
 
                 } else {
 
-                    let mut index = 0;
                     if self.show_imgs {
-                        for img in self.imgs.iter() {
+                        for img in self.imgs.clone() {
                             ui.group(|ui| {
-                                ui.image(img.0.texture_id(ui.ctx()), if img.0.size_vec2().max_elem() > self.size as f32 {
-                                    img.0.size_vec2().normalized().mul(Vec2{x: self.size as f32, y: self.size as f32})
-                                } else {
-                                    img.0.size_vec2()
-                                });
-                                if let Some((index, form)) = more_info_bar(ui, index, &Form::Photo) {
-                                    self.picked = Some(index);
+                                self.thumbnail_ui(ui, img);
+                                if let Some((id, form)) = more_info_bar(ui, img, &Form::Photo, describe(&self.library, &img)) {
+                                    self.picked = Some(id);
                                     self.picktype = form;
                                 }
                             });
-                            index += 1;
                         }
                     }
                     if self.show_ptxts {
-                        index = 0;
-                        for ptxt in self.ptxts.iter() {
+                        for ptxt in self.ptxts.clone() {
                             ui.group(|ui| {
                                 ui.set_max_height(256.0);
-                                ui.label(truncate_dotted(ptxt.0.clone(), self.size));
-                                if let Some((index, form)) = more_info_bar(ui, index, &Form::PlainText) {
-                                    self.picked = Some(index);
+                                self.preview_ui(ui, ptxt);
+                                if let Some((id, form)) = more_info_bar(ui, ptxt, &Form::PlainText, describe(&self.library, &ptxt)) {
+                                    self.picked = Some(id);
                                     self.picktype = form;
                                 }
                             });
-                            index += 1;
                         }
                     }
                     if self.show_bins {
-                        index = 0;
-                        for bin in self.bins.iter() {
+                        for bin in self.bins.clone() {
                             ui.group(|ui| {
                                 ui.set_max_height(256.0);
-                                ui.label(truncate_dotted(bin.0.clone(), self.size));
-                                if let Some((index, form)) = more_info_bar(ui, index, &Form::Binary) {
-                                    self.picked = Some(index);
+                                self.preview_ui(ui, bin);
+                                let form = self.library.get(&bin).map_or(Form::Binary, |object| object.form.clone());
+                                if let Some((id, form)) = more_info_bar(ui, bin, &form, describe(&self.library, &bin)) {
+                                    self.picked = Some(id);
                                     self.picktype = form;
                                 }
                             });
-                            index += 1;
                         }
                     }
                 }
@@ -236,25 +701,10 @@ impl eframe::App for MyApp {
                         }
 
                         if ui.button("Yes!").clicked() {
-                            match self.picktype {
-                                Form::Photo => {
-                                    self.objects.remove(&self.imgs[self.picked.unwrap()].1);
-                                    self.picked = None;
-                                    self.picktype = Form::Empty;
-                                }
-                                Form::Empty => {
-                                },
-                                Form::PlainText => {
-                                    self.objects.remove(&self.ptxts[self.picked.unwrap()].1);
-                                    self.picked = None;
-                                    self.picktype = Form::Empty;
-                                },
-                                Form::Binary => {
-                                    self.objects.remove(&self.bins[self.picked.unwrap()].1);
-                                    self.picked = None;
-                                    self.picktype = Form::Binary;
-                                }
-                                _ => {}
+                            if let Some(picked) = self.picked {
+                                self.library.remove(&picked);
+                                self.picked = None;
+                                self.picktype = Form::Empty;
                             }
                             self.refresh();
                         }
@@ -262,6 +712,28 @@ impl eframe::App for MyApp {
                 });
         }
 
+        if self.show_imports {
+            let mut open = true;
+            egui::Window::new("Imports")
+                .collapsible(false)
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    self.imports_ui(ui);
+                });
+            self.show_imports = open;
+        }
+
+        if self.show_similar {
+            let mut open = true;
+            egui::Window::new("Similar photos")
+                .collapsible(false)
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    self.similar_ui(ui);
+                });
+            self.show_similar = open;
+        }
+
         if self.show_show {
             egui::Window::new("Show/hide")
                 .collapsible(false)
@@ -270,7 +742,7 @@ impl eframe::App for MyApp {
                     ui.horizontal(|ui| {
                         ui.checkbox(&mut self.show_imgs, "Photos");
                         ui.checkbox(&mut self.show_ptxts, "Plain texts");
-                        ui.checkbox(&mut self.show_bins, "Binaries");
+                        ui.checkbox(&mut self.show_bins, "Other forms");
                     });
                 });
         }
@@ -281,14 +753,29 @@ impl eframe::App for MyApp {
                 .collapsible(false)
                 .resizable(false)
                 .show(ctx, |ui| {
+                    // The changes would be lost, so quitting anyway takes another click.
+                    if self.save_failed {
+                        ui.colored_label(ui.visuals().error_fg_color, "⚠ The changes could not be saved, see the errors below.");
+                    }
                     ui.horizontal(|ui| {
                         if ui.button("Cancel").clicked() {
                             self.show_confirmation_dialog = false;
+                            self.save_failed = false;
                         }
 
-                        if ui.button("Yes!").clicked() {
+                        let mut quit = false;
+                        if ui.button(if self.save_failed { "Try again" } else { "Yes!" }).clicked() {
+                            quit = self.save();
+                            self.save_failed = !quit;
+                        }
+                        if self.save_failed && ui.button("Quit without saving").clicked() {
+                            quit = true;
+                        }
+                        if quit {
                             self.allowed_to_close = true;
-                            save_objects(&self.objects, "object_store");
+                            if let Some(scratch) = &self.scratch {
+                                let _ = fs::remove_dir_all(scratch);
+                            }
                             frame.close();
                         }
                     });
@@ -336,63 +823,62 @@ fn preview_files_being_dropped(ctx: &egui::Context) {
     }
 }
 
-pub fn load_objects(store: &str) -> HashSet<Object> {
-    let mut objects: HashSet<Object> = HashSet::new();
-    if let Some(mut file) = File::open(store).ok() {
-        let mut data = vec![];
-        if let Some(_) = file.read_to_end(&mut data).ok() {
-            objects = serde_json::from_str(String::from_utf8_lossy(data.as_slice()).to_string().as_str()).unwrap();
-        }
+pub fn truncate_dotted(s: String, to: usize) -> String {
+    if to > 3 && s.len() > to {
+        let mut s = s.chars().take(to-3).collect::<String>();
+        s.push_str("…");
+        s
+    } else {
+        s
     }
-    objects
 }
 
-pub fn save_objects(objects: &HashSet<Object>, store: &str) {
-    if let Some(mut file) = File::create(store).ok() {
-        let data = serde_json::to_string(&objects).unwrap();
-        let _ = file.write(data.as_bytes()).unwrap();
-    }
+fn retained(id: ObjectId, image: image::RgbaImage) -> RetainedImage {
+    let size = [image.width() as usize, image.height() as usize];
+    RetainedImage::from_color_image(id.to_string(), egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw()))
 }
 
-pub fn import_file(path: &str, objects: &mut HashSet<Object>) -> Option<()> {
-    let mut file = File::open(path).ok()?;
-    let mut data = vec![];
-    let mut _len = file.read_to_end(&mut data).ok()?;
-    import_file_bytes(data, objects)
+// The start of an object's data, as text for plain texts and as hex for everything else.
+fn read_text(library: &Library, id: &ObjectId, length: usize) -> String {
+    let Some(object) = library.get(id) else {
+        return String::new();
+    };
+    match object.data.read_prefix(length) {
+        Ok(data) => {
+            let mut text = match object.form {
+                Form::PlainText => String::from_utf8_lossy(data.as_slice()).into_owned(),
+                _ => hex::encode(data.as_slice()),
+            };
+            if (data.len() as u64) < object.data.size() {
+                text.push('…');
+            }
+            text
+        }
+        Err(e) => format!("⚠ {e}"),
+    }
 }
 
-pub fn import_file_bytes(data: Vec<u8>, objects: &mut HashSet<Object>) -> Option<()> {
-    let form = magic_identify(data.as_slice());
-    match form {
-        Form::PlainText => objects.insert(plain_text(String::from_utf8_lossy(data.as_slice()).to_string())),
-        Form::Photo => objects.insert(photo(data)),
-        _ => objects.insert(binary(data)),
-    };
-    Some(())
+fn merge_policy_ui(ui: &mut egui::Ui, id: &str, policy: &mut MergePolicy) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(MERGE_POLICIES.iter().find(|(p, _)| p == policy).map_or("", |(_, name)| *name))
+        .show_ui(ui, |ui| {
+            for (p, name) in MERGE_POLICIES {
+                ui.selectable_value(policy, p, name);
+            }
+        });
 }
 
-pub fn truncate_dotted(s: String, to: usize) -> String {
-    if to > 3 && s.len() > to {
-        let mut s = s.chars().take(to-3).collect::<String>();
-        s.push_str("…");
-        s
-    } else {
-        s
-    }
+fn describe(library: &Library, id: &ObjectId) -> String {
+    library.get(id).map(|object| object.describe()).unwrap_or_default()
 }
 
-fn more_info_bar(ui: &mut egui::Ui, index: usize, form: &Form) -> Option<(usize, Form)>{
+fn more_info_bar(ui: &mut egui::Ui, id: ObjectId, form: &Form, label: String) -> Option<(ObjectId, Form)>{
     let mut result = None;
     ui.horizontal(|ui| {
         if ui.button("🔧").clicked() {
-            result = Some((index, form.clone()));
+            result = Some((id, form.clone()));
         }
-        match form.clone() {
-            Form::PlainText => ui.label("Text (Plain)"),
-            Form::Photo => ui.label("Photo"),
-            Form::Binary => ui.label("Binary (Unknown format)"),
-            _ => ui.label("Unknown"),
-        };
+        ui.label(label);
     });
     result
 }
\ No newline at end of file
@@ -1,32 +1,164 @@
 use std::collections::HashSet;
+use std::io::{BufRead, Cursor, Read, Seek};
 
 
 
-use crate::object::{Form, Object, Tag};
+use crate::object::{DateConcerns, DateTime, Form, Location, Object, ObjectId, Tag};
 
 
 pub fn empty() -> Object {
-    Object {data: vec![], tags: HashSet::new(), form: Form::Empty}
+    Object {id: ObjectId::of(&[]), data: vec![], tags: HashSet::new(), form: Form::Empty}
 }
 
 pub fn plain_text(data: String) -> Object {
-    Object {data: data.as_bytes().to_vec(), tags: HashSet::new(), form: Form::PlainText}
+    Object {id: ObjectId::of(data.as_bytes()), data: data.as_bytes().to_vec(), tags: HashSet::new(), form: Form::PlainText}
 }
 
 pub fn binary(data: Vec<u8>) -> Object {
-    Object {data: data.clone(), tags: HashSet::new(), form: Form::Binary}
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::Binary}
+}
+
+pub fn typeset_text(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::TypesetText}
+}
+
+pub fn app(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::App}
+}
+
+pub fn sound(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::Sound}
+}
+
+pub fn video(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::Video}
+}
+
+pub fn model3d(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::Model3D}
+}
+
+pub fn archive(data: Vec<u8>) -> Object {
+    Object {id: ObjectId::of(data.as_slice()), data, tags: HashSet::new(), form: Form::Archive}
 }
 
 pub fn photo(data: Vec<u8>) -> Object {
-    let mut object = Object {data: data.clone(), tags: HashSet::new(), form: Form::Photo};
-    if let Some(fields) = exif::parse_exif(data.as_slice()).ok() {
-        for field in fields.0 {
-            let tag = field.tag.description().unwrap_or(field.tag.number().to_string().as_str()).to_string();
+    let mut object = Object {id: ObjectId::of(data.as_slice()), data: (), tags: HashSet::new(), form: Form::Photo};
+    add_exif(&mut object, &mut Cursor::new(data.as_slice()));
+    Object {id: object.id, data, tags: object.tags, form: object.form}
+}
+
+// How much of a bare TIFF is read for its EXIF fields, see `add_exif`.
+pub const TIFF_EXIF_BYTES: u64 = 16 * 1024 * 1024;
+
+// Tags an object with the EXIF fields of a photo, read from the container rather than from
+// memory so that large files needn't be loaded whole. The reader finds the EXIF attributes in
+// JPEG, PNG, HEIF and WebP files by seeking to them. In a bare TIFF they may be anywhere, and the
+// reader would load the whole file, so only its first TIFF_EXIF_BYTES are read; fields beyond
+// that are lost.
+pub fn add_exif<D>(object: &mut Object<D>, container: &mut (impl BufRead + Seek)) {
+    let tiff = container.fill_buf().is_ok_and(|start| start.starts_with(b"II*\0") || start.starts_with(b"MM\0*"));
+    let exif = if tiff {
+        let mut data = vec![];
+        container.take(TIFF_EXIF_BYTES).read_to_end(&mut data).ok().and_then(|_| exif::Reader::new().read_raw(data).ok())
+    } else {
+        exif::Reader::new().read_from_container(container).ok()
+    };
+    if let Some(exif) = exif {
+        for field in exif.fields() {
+            let tag = if field.tag.description().is_some() {
+                field.tag.to_string()
+            } else {
+                field.tag.number().to_string()
+            };
             let value = field.display_value().to_string();
-            object.tags.insert(Tag::Exif {tag, value});
+            object.tags.insert(Tag::Exif {tag, value, ifd: field.ifd_num.index()});
+        }
+        for tag in exif_tags(&exif) {
+            object.add_tag(tag).ok();
         }
     }
-    object
+}
+
+// Dates, authors and the location from the EXIF fields of the primary image. The original date
+// is when the photo was created, and falls back to when it was digitized.
+fn exif_tags(exif: &exif::Exif) -> Vec<Tag> {
+    let field = |tag| exif.get_field(tag, exif::In::PRIMARY).map(|field| &field.value);
+    let ascii = |tag| match field(tag) {
+        Some(exif::Value::Ascii(strings)) => strings.iter().map(|s| String::from_utf8_lossy(s).trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        _ => vec![],
+    };
+    let date = |tag, offset_tag| {
+        let date = ascii(tag).first().and_then(|s| DateTime::parse_exif(s))?;
+        let offset = ascii(offset_tag).first().and_then(|s| DateTime::parse_utc_offset(s));
+        Some(date.clone().with_offset(offset).unwrap_or(date))
+    };
+    let mut tags = vec![];
+    let created = date(exif::Tag::DateTimeOriginal, exif::Tag::OffsetTimeOriginal)
+        .or_else(|| date(exif::Tag::DateTimeDigitized, exif::Tag::OffsetTimeDigitized));
+    if let Some(value) = created {
+        tags.push(Tag::Date {value, concerns: DateConcerns::Created});
+    }
+    if let Some(value) = date(exif::Tag::DateTime, exif::Tag::OffsetTime) {
+        tags.push(Tag::Date {value, concerns: DateConcerns::Edited});
+    }
+    // Several artists are separated by semicolons.
+    for artist in ascii(exif::Tag::Artist) {
+        tags.extend(artist.split(';').map(str::trim).filter(|s| !s.is_empty()).map(|s| Tag::Author(s.into())));
+    }
+    // The copyrights of the photographer and the editor are separate strings.
+    for copyright in ascii(exif::Tag::Copyright) {
+        if let Some(holder) = copyright_holder(&copyright) {
+            tags.push(Tag::Author(holder));
+        }
+    }
+    let degrees = |tag, reference, negative| match (field(tag), ascii(reference).first()) {
+        (Some(exif::Value::Rational(dms)), Some(reference)) if dms.len() == 3 => {
+            let degrees = dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0;
+            Some(if reference.eq_ignore_ascii_case(negative) {-degrees} else {degrees})
+        }
+        _ => None,
+    };
+    let latitude = degrees(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S");
+    let longitude = degrees(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W");
+    let altitude = match field(exif::Tag::GPSAltitude) {
+        Some(exif::Value::Rational(altitude)) if !altitude.is_empty() => {
+            let below_sea_level = field(exif::Tag::GPSAltitudeRef).and_then(|value| value.get_uint(0)) == Some(1);
+            Some(if below_sea_level {-altitude[0].to_f64()} else {altitude[0].to_f64()})
+        }
+        _ => None,
+    };
+    if let Some(location) = latitude.zip(longitude).and_then(|(latitude, longitude)| Location::new(latitude, longitude, altitude)) {
+        tags.push(Tag::Location(location));
+    }
+    tags
+}
+
+// The holder of a copyright such as "© 2020 Jane Doe" or "Copyright (c) 2019-2020, Jane Doe. All
+// rights reserved.".
+fn copyright_holder(copyright: &str) -> Option<String> {
+    let mut holder = copyright.trim();
+    // Strips the copyright signs and years in front of the name one at a time.
+    loop {
+        let sign = ["©", "(c)", "copyright", ",", "-"].iter()
+            .find(|sign| holder.get(..sign.len()).is_some_and(|s| s.eq_ignore_ascii_case(sign)))
+            .map(|sign| &holder[sign.len()..]);
+        let year = holder.get(..4)
+            .filter(|year| year.bytes().all(|b| b.is_ascii_digit()) && !holder[4..].starts_with(char::is_alphanumeric))
+            .map(|_| &holder[4..]);
+        match sign.or(year) {
+            Some(rest) => holder = rest.trim_start(),
+            None => break,
+        }
+    }
+    // The name ends with its sentence, though not with an initial, or where the rights are stated.
+    let lower = holder.to_ascii_lowercase();
+    let mut end = ["all rights reserved", "some rights reserved"].iter().filter_map(|phrase| lower.find(phrase)).min().unwrap_or(holder.len());
+    if let Some((i, _)) = holder[..end].match_indices(". ").find(|(i, _)| holder[..*i].rsplit(' ').next().is_some_and(|word| word.chars().count() > 1)) {
+        end = i;
+    }
+    let holder = holder[..end].trim_end_matches(|c: char| c.is_whitespace() || ".,;:".contains(c));
+    (!holder.is_empty()).then(|| holder.to_string())
 }
 
 /*
@@ -68,4 +200,166 @@ fn readablificate(data: &Vec<u8>) -> String {
     }
     string
 }
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader, SeekFrom};
+    use exif::experimental::Writer;
+    use exif::{Field, In, Value};
+    use super::*;
+
+    fn tiff(fields: &[Field]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(vec![]);
+        writer.write(&mut tiff, true).unwrap();
+        tiff.into_inner()
+    }
+
+    fn ascii(tag: exif::Tag, ifd_num: In, value: &str) -> Field {
+        Field {tag, ifd_num, value: Value::Ascii(vec![value.as_bytes().to_vec()])}
+    }
+
+    fn exif_object(data: &[u8]) -> Object<()> {
+        let mut object = Object {id: ObjectId::of(data), data: (), tags: HashSet::new(), form: Form::Photo};
+        add_exif(&mut object, &mut Cursor::new(data));
+        object
+    }
+
+    // A file of `data` followed by zeros up to `length`, which counts how much of it is read.
+    struct Padded {
+        data: Vec<u8>,
+        length: u64,
+        position: u64,
+        read: u64,
+    }
+
+    impl Read for Padded {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = (buf.len() as u64).min(self.length.saturating_sub(self.position)) as usize;
+            buf[..n].fill(0);
+            if let Some(data) = self.data.get(self.position as usize..) {
+                let copied = n.min(data.len());
+                buf[..copied].copy_from_slice(&data[..copied]);
+            }
+            self.position += n as u64;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Padded {
+        fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+            self.position = match from {
+                SeekFrom::Start(n) => n,
+                SeekFrom::End(n) => self.length.saturating_add_signed(n),
+                SeekFrom::Current(n) => self.position.saturating_add_signed(n),
+            };
+            Ok(self.position)
+        }
+    }
+
+    fn rationals(values: &[(u32, u32)]) -> Value {
+        Value::Rational(values.iter().map(|&(num, denom)| exif::Rational {num, denom}).collect())
+    }
+
+    fn date(value: &str, concerns: DateConcerns) -> Tag {
+        Tag::Date {value: value.parse::<DateTime>().ok().unwrap(), concerns}
+    }
+
+    fn location(object: &Object<()>) -> Option<(f64, f64, Option<f64>)> {
+        object.tags.iter().find_map(|tag| match tag {
+            Tag::Location(location) => Some((location.latitude(), location.longitude(), location.altitude())),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn dates() {
+        let digitized = exif_object(&tiff(&[
+            ascii(exif::Tag::DateTimeDigitized, In::PRIMARY, "2020:01:15 10:30:00"),
+            ascii(exif::Tag::OffsetTimeDigitized, In::PRIMARY, "+02:00"),
+            ascii(exif::Tag::DateTime, In::PRIMARY, "2021:03:02 08:00:00"),
+        ]));
+        assert!(digitized.tags.contains(&date("2020-01-15T10:30:00+02:00", DateConcerns::Created)));
+        assert!(digitized.tags.contains(&date("2021-03-02T08:00:00", DateConcerns::Edited)));
+        let original = exif_object(&tiff(&[
+            ascii(exif::Tag::DateTimeOriginal, In::PRIMARY, "2019:07:01 12:00:00"),
+            ascii(exif::Tag::DateTimeDigitized, In::PRIMARY, "2020:01:15 10:30:00"),
+            ascii(exif::Tag::DateTime, In::PRIMARY, "not a date"),
+        ]));
+        let dates = original.tags.iter().filter(|tag| matches!(tag, Tag::Date {..})).collect::<Vec<_>>();
+        assert!(dates == [&date("2019-07-01T12:00:00", DateConcerns::Created)]);
+    }
+
+    #[test]
+    fn locations() {
+        let south_west = exif_object(&tiff(&[
+            ascii(exif::Tag::GPSLatitudeRef, In::PRIMARY, "S"),
+            Field {tag: exif::Tag::GPSLatitude, ifd_num: In::PRIMARY, value: rationals(&[(33, 1), (51, 1), (36, 1)])},
+            ascii(exif::Tag::GPSLongitudeRef, In::PRIMARY, "W"),
+            Field {tag: exif::Tag::GPSLongitude, ifd_num: In::PRIMARY, value: rationals(&[(70, 1), (39, 2), (0, 1)])},
+            Field {tag: exif::Tag::GPSAltitudeRef, ifd_num: In::PRIMARY, value: Value::Byte(vec![1])},
+            Field {tag: exif::Tag::GPSAltitude, ifd_num: In::PRIMARY, value: rationals(&[(415, 10)])},
+        ]));
+        let (latitude, longitude, altitude) = location(&south_west).unwrap();
+        assert!((latitude + 33.86).abs() < 1e-6 && (longitude + 70.325).abs() < 1e-6 && altitude == Some(-41.5));
+        let north_east = exif_object(&tiff(&[
+            ascii(exif::Tag::GPSLatitudeRef, In::PRIMARY, "N"),
+            Field {tag: exif::Tag::GPSLatitude, ifd_num: In::PRIMARY, value: rationals(&[(48, 1), (51, 1), (0, 1)])},
+            ascii(exif::Tag::GPSLongitudeRef, In::PRIMARY, "E"),
+            Field {tag: exif::Tag::GPSLongitude, ifd_num: In::PRIMARY, value: rationals(&[(2, 1), (21, 1), (0, 1)])},
+            Field {tag: exif::Tag::GPSAltitude, ifd_num: In::PRIMARY, value: rationals(&[(35, 1)])},
+        ]));
+        let (latitude, longitude, altitude) = location(&north_east).unwrap();
+        assert!((latitude - 48.85).abs() < 1e-6 && (longitude - 2.35).abs() < 1e-6 && altitude == Some(35.0));
+        // Without its reference a coordinate can't be told from its opposite.
+        let unknown = exif_object(&tiff(&[
+            Field {tag: exif::Tag::GPSLatitude, ifd_num: In::PRIMARY, value: rationals(&[(48, 1), (51, 1), (0, 1)])},
+            ascii(exif::Tag::GPSLongitudeRef, In::PRIMARY, "E"),
+            Field {tag: exif::Tag::GPSLongitude, ifd_num: In::PRIMARY, value: rationals(&[(2, 1), (21, 1), (0, 1)])},
+        ]));
+        assert!(location(&unknown).is_none());
+    }
+
+    #[test]
+    fn authors() {
+        let object = exif_object(&tiff(&[
+            ascii(exif::Tag::Artist, In::PRIMARY, "Jane Doe; John Roe ;"),
+            Field {tag: exif::Tag::Copyright, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Copyright 2020 Ann Example. All rights reserved.".to_vec(), b"All rights reserved".to_vec()])},
+        ]));
+        let mut authors = object.tags.iter().filter_map(|tag| match tag {
+            Tag::Author(author) => Some(author.as_str()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        authors.sort();
+        assert!(authors == ["Ann Example", "Jane Doe", "John Roe"]);
+    }
+
+    #[test]
+    fn copyright_holders() {
+        assert!(copyright_holder("© 2020 Jane Doe") == Some("Jane Doe".into()));
+        assert!(copyright_holder("Copyright (c) 2019-2020, Jane Doe") == Some("Jane Doe".into()));
+        assert!(copyright_holder("Copyright 2020 Jane Doe. All rights reserved.") == Some("Jane Doe".into()));
+        assert!(copyright_holder("(C) J. R. Doe, all rights reserved") == Some("J. R. Doe".into()));
+        assert!(copyright_holder("Jane Doe Photography Ltd. Licensed for web use only.") == Some("Jane Doe Photography Ltd".into()));
+        assert!(copyright_holder("2020 Studio 54") == Some("Studio 54".into()));
+        assert!(copyright_holder("All rights reserved").is_none());
+        assert!(copyright_holder("© 2020. All Rights Reserved.").is_none());
+        assert!(copyright_holder("  ").is_none());
+    }
+
+    #[test]
+    fn large_tiffs_are_read_in_part() {
+        let data = tiff(&[ascii(exif::Tag::Artist, In::PRIMARY, "Jane Doe")]);
+        assert!(exif_object(data.as_slice()).tags.contains(&Tag::Author("Jane Doe".into())));
+        let mut file = Padded {data, length: 1 << 40, position: 0, read: 0};
+        let mut object = Object {id: ObjectId::of(b""), data: (), tags: HashSet::new(), form: Form::Photo};
+        add_exif(&mut object, &mut BufReader::new(&mut file));
+        assert!(object.tags.contains(&Tag::Author("Jane Doe".into())));
+        assert!(file.read <= TIFF_EXIF_BYTES + 8192);
+    }
+}
@@ -0,0 +1,408 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while_m_n};
+use nom::character::complete::{alpha1, char, digit1, multispace0, multispace1, one_of};
+use nom::combinator::{all_consuming, map, map_opt, map_res, opt, recognize, value};
+use nom::error::{Error, ErrorKind};
+use nom::sequence::{pair, preceded, tuple};
+use nom::IResult;
+use serde::{Serialize, Deserialize};
+
+// A date and time known to some precision, from only the year down to the second. The known
+// fields always lead: there is no day without a month. Times with an offset from UTC are
+// instants; times without one are local to wherever they were taken.
+#[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Clone)]
+pub struct DateTime {
+    year: Option<i32>,
+    month: Option<u8>,
+    day: Option<u8>,
+    hour: Option<u8>,
+    minute: Option<u8>,
+    second: Option<u8>,
+    offset: Option<i16>, // Minutes east of UTC.
+}
+
+impl Display for DateTime {
+    // ISO 8601 down to the known precision, e.g. "2020-01", "2020-01-15T10:30" or "2020-01-15T10:30:00+01:00".
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.year {
+            Some(year) if (0..=9999).contains(&year) => write!(f, "{year:04}")?,
+            Some(year) => write!(f, "{year:+05}")?,
+            None => return f.write_str("????"),
+        }
+        if let Some(month) = self.month {
+            write!(f, "-{month:02}")?;
+        }
+        if let Some(day) = self.day {
+            write!(f, "-{day:02}")?;
+        }
+        if let Some(hour) = self.hour {
+            write!(f, "T{hour:02}")?;
+        }
+        if let Some(minute) = self.minute {
+            write!(f, ":{minute:02}")?;
+        }
+        if let Some(second) = self.second {
+            write!(f, ":{second:02}")?;
+        }
+        match self.offset {
+            Some(0) => f.write_str("Z"),
+            Some(offset) => write!(f, "{}{:02}:{:02}", if offset < 0 {'-'} else {'+'}, offset.abs() / 60, offset.abs() % 60),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = String;
+
+    // Reads ISO 8601, EXIF and RFC 2822 dates, e.g. "2020-01-15T10:30:00+01:00",
+    // "2020:01:15 10:30:00" or "Wed, 15 Jan 2020 10:30:00 +0100".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        DateTime::parse_iso8601(s)
+            .or_else(|| DateTime::parse_exif(s))
+            .or_else(|| DateTime::parse_rfc2822(s))
+            .ok_or(format!("{s} is not a date"))
+    }
+}
+
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DateTime {
+    // Earlier first, comparing in UTC where the offset is known. A date that is only partly
+    // known comes before the more precise dates it could be, so 2020 < 2020-01 < 2020-01-01.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.utc_fields().cmp(&other.utc_fields()).then(self.offset.cmp(&other.offset))
+    }
+}
+
+impl DateTime {
+    // Returns None unless the known fields lead and make a valid date and time. A second of 60 is
+    // allowed for leap seconds.
+    pub fn new(year: Option<i32>, month: Option<u8>, day: Option<u8>, hour: Option<u8>, minute: Option<u8>, second: Option<u8>) -> Option<DateTime> {
+        let known = [year.is_some(), month.is_some(), day.is_some(), hour.is_some(), minute.is_some(), second.is_some()];
+        if known.windows(2).any(|pair| !pair[0] && pair[1]) {
+            return None;
+        }
+        let valid = month.is_none_or(|month| (1..=12).contains(&month))
+            && day.is_none_or(|day| day >= 1 && day <= days_in_month(year.unwrap_or_default(), month.unwrap_or_default()))
+            && hour.is_none_or(|hour| hour < 24)
+            && minute.is_none_or(|minute| minute < 60)
+            && second.is_none_or(|second| second <= 60);
+        valid.then_some(DateTime {year, month, day, hour, minute, second, offset: None})
+    }
+
+    // The same date and time at `offset` minutes east of UTC. Returns None if the offset is a day
+    // or more, or if the time of day isn't known.
+    pub fn with_offset(self, offset: Option<i16>) -> Option<DateTime> {
+        match offset {
+            Some(offset) if offset.abs() >= 24 * 60 || self.hour.is_none() => None,
+            _ => Some(DateTime {offset, ..self}),
+        }
+    }
+
+    // The date and time in UTC.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year: Some(year as i32),
+            month: Some(month as u8),
+            day: Some(day as u8),
+            hour: Some((seconds / 3600) as u8),
+            minute: Some((seconds / 60 % 60) as u8),
+            second: Some((seconds % 60) as u8),
+            offset: Some(0),
+        }
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.year
+    }
+
+    pub fn month(&self) -> Option<u8> {
+        self.month
+    }
+
+    pub fn day(&self) -> Option<u8> {
+        self.day
+    }
+
+    pub fn hour(&self) -> Option<u8> {
+        self.hour
+    }
+
+    pub fn minute(&self) -> Option<u8> {
+        self.minute
+    }
+
+    pub fn second(&self) -> Option<u8> {
+        self.second
+    }
+
+    pub fn offset(&self) -> Option<i16> {
+        self.offset
+    }
+
+    // ISO 8601 in the extended format, as written by Display, e.g. "2020-01-15T10:30:00.25+01:00".
+    // Parts may be left off from the end, and fractions of seconds are dropped.
+    pub fn parse_iso8601(input: &str) -> Option<DateTime> {
+        all_consuming(iso8601)(input).ok().map(|(_, date)| date)
+    }
+
+    // EXIF's "YYYY:MM:DD HH:MM:SS", where unknown parts at the end are left blank.
+    pub fn parse_exif(input: &str) -> Option<DateTime> {
+        all_consuming(exif)(input.trim_end_matches([' ', ':'])).ok().map(|(_, date)| date)
+    }
+
+    // RFC 2822 as used in mail headers, e.g. "Wed, 15 Jan 2020 10:30:00 +0100". A zone of -0000
+    // means the offset is unknown.
+    pub fn parse_rfc2822(input: &str) -> Option<DateTime> {
+        all_consuming(rfc2822)(input).ok().map(|(_, date)| date)
+    }
+
+    // An offset from UTC in minutes, written Z, ±HH:MM, ±HHMM or ±HH as in ISO 8601 and EXIF's
+    // OffsetTime fields.
+    pub fn parse_utc_offset(input: &str) -> Option<i16> {
+        all_consuming(utc_offset)(input).ok().map(|(_, offset)| offset)
+    }
+
+    // Compares two dates only as far as both are known, so 2020-01 is equal to any day in January 2020.
+    pub fn cmp_prefix(&self, other: &DateTime) -> Ordering {
+        let (a, b) = if self.offset.is_some() && other.offset.is_some() {
+            (self.utc_fields(), other.utc_fields())
+        } else {
+            (self.fields(), other.fields())
+        };
+        for (a, b) in a.into_iter().zip(b) {
+            match (a, b) {
+                (Some(a), Some(b)) if a != b => return a.cmp(&b),
+                (Some(_), Some(_)) => {}
+                _ => break,
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn fields(&self) -> [Option<i64>; 6] {
+        [self.year.map(i64::from), self.month.map(i64::from), self.day.map(i64::from),
+            self.hour.map(i64::from), self.minute.map(i64::from), self.second.map(i64::from)]
+    }
+
+    // The fields moved to UTC if the offset and the time down to the minute are known.
+    fn utc_fields(&self) -> [Option<i64>; 6] {
+        let [year, month, day, hour, minute, second] = self.fields();
+        match (self.offset, year, month, day, hour, minute) {
+            (Some(offset), Some(year), Some(month), Some(day), Some(hour), Some(minute)) => {
+                let minutes = (days_from_civil(year, month, day) * 24 + hour) * 60 + minute - i64::from(offset);
+                let (days, minutes) = (minutes.div_euclid(24 * 60), minutes.rem_euclid(24 * 60));
+                let (year, month, day) = civil_from_days(days);
+                [Some(year), Some(month), Some(day), Some(minutes / 60), Some(minutes % 60), second]
+            }
+            _ => [year, month, day, hour, minute, second],
+        }
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+// February has 29 days if the year isn't known.
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Converts between days since 1970-01-01 and proleptic Gregorian dates, see
+// https://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Fails the parser at `input` if the fields don't make a valid date.
+fn valid(input: &str, date: Option<DateTime>) -> IResult<&str, DateTime> {
+    date.map(|date| (input, date)).ok_or(nom::Err::Error(Error::new(input, ErrorKind::Verify)))
+}
+
+// YYYY[-MM[-DD[(T| )HH[:MM[:SS[.s]]][Z|±HH[:MM]]]]], with years beyond 9999 written with a sign.
+fn iso8601(input: &str) -> IResult<&str, DateTime> {
+    let (input, year) = alt((
+        map_res(recognize(pair(one_of("+-"), digit1)), |s: &str| s.parse::<i32>()),
+        map_res(take_while_m_n(4, 4, |c: char| c.is_ascii_digit()), |s: &str| s.parse::<i32>()),
+    ))(input)?;
+    let (input, month) = opt(preceded(char('-'), two_digits))(input)?;
+    let (input, day) = if month.is_some() { opt(preceded(char('-'), two_digits))(input)? } else { (input, None) };
+    let (input, hour) = if day.is_some() { opt(preceded(one_of("Tt "), two_digits))(input)? } else { (input, None) };
+    let (input, minute) = if hour.is_some() { opt(preceded(char(':'), two_digits))(input)? } else { (input, None) };
+    let (input, second) = if minute.is_some() { opt(preceded(char(':'), two_digits))(input)? } else { (input, None) };
+    let (input, _) = if second.is_some() { opt(preceded(one_of(".,"), digit1))(input)? } else { (input, None) };
+    let (input, offset) = if hour.is_some() { opt(utc_offset)(input)? } else { (input, None) };
+    valid(input, DateTime::new(Some(year), month, day, hour, minute, second).and_then(|date| date.with_offset(offset)))
+}
+
+fn utc_offset(input: &str) -> IResult<&str, i16> {
+    alt((
+        value(0, one_of("Zz")),
+        map_opt(tuple((one_of("+-"), two_digits, opt(preceded(opt(char(':')), two_digits)))), |(sign, hours, minutes)| {
+            let minutes = minutes.unwrap_or(0);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = i16::from(hours) * 60 + i16::from(minutes);
+            Some(if sign == '-' { -offset } else { offset })
+        }),
+    ))(input)
+}
+
+fn exif(input: &str) -> IResult<&str, DateTime> {
+    let (input, year) = map_res(take_while_m_n(4, 4, |c: char| c.is_ascii_digit()), |s: &str| s.parse::<i32>())(input)?;
+    let (input, month) = opt(preceded(char(':'), two_digits))(input)?;
+    let (input, day) = if month.is_some() { opt(preceded(char(':'), two_digits))(input)? } else { (input, None) };
+    let (input, hour) = if day.is_some() { opt(preceded(char(' '), two_digits))(input)? } else { (input, None) };
+    let (input, minute) = if hour.is_some() { opt(preceded(char(':'), two_digits))(input)? } else { (input, None) };
+    let (input, second) = if minute.is_some() { opt(preceded(char(':'), two_digits))(input)? } else { (input, None) };
+    valid(input, DateTime::new(Some(year), month, day, hour, minute, second))
+}
+
+fn rfc2822(input: &str) -> IResult<&str, DateTime> {
+    let (input, _) = opt(tuple((alpha1, char(','), multispace0)))(input)?;
+    let (input, day) = map_res(take_while_m_n(1, 2, |c: char| c.is_ascii_digit()), |s: &str| s.parse::<u8>())(input)?;
+    let (input, month) = preceded(multispace1, map_opt(alpha1, month_named))(input)?;
+    // Two and three digit years are obsolete, but still found in old mail.
+    let (input, year) = preceded(multispace1, map_res(digit1, |s: &str| s.parse::<i32>().map(|year| match s.len() {
+        2 if year < 50 => year + 2000,
+        2 | 3 => year + 1900,
+        _ => year,
+    })))(input)?;
+    let (input, hour) = preceded(multispace1, two_digits)(input)?;
+    let (input, minute) = preceded(char(':'), two_digits)(input)?;
+    let (input, second) = opt(preceded(char(':'), two_digits))(input)?;
+    let (input, offset) = preceded(multispace1, alt((
+        value(None, tag("-0000")),
+        map(utc_offset, Some),
+        map_opt(alpha1, zone_named),
+    )))(input)?;
+    valid(input, DateTime::new(Some(year), Some(month), Some(day), Some(hour), Some(minute), second).and_then(|date| date.with_offset(offset)))
+}
+
+fn month_named(name: &str) -> Option<u8> {
+    let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    months.iter().position(|month| name.eq_ignore_ascii_case(month)).map(|i| i as u8 + 1)
+}
+
+// The zones RFC 2822 names. Military zones other than Z were used inconsistently, so they
+// aren't trusted.
+fn zone_named(name: &str) -> Option<Option<i16>> {
+    match name.to_uppercase().as_str() {
+        "UT" | "GMT" | "Z" => Some(Some(0)),
+        "EDT" => Some(Some(-4 * 60)),
+        "EST" | "CDT" => Some(Some(-5 * 60)),
+        "CST" | "MDT" => Some(Some(-6 * 60)),
+        "MST" | "PDT" => Some(Some(-7 * 60)),
+        "PST" => Some(Some(-8 * 60)),
+        name if name.len() == 1 => Some(None),
+        _ => None,
+    }
+}
+
+fn two_digits(input: &str) -> IResult<&str, u8> {
+    map_res(take_while_m_n(2, 2, |c: char| c.is_ascii_digit()), |s: &str| s.parse::<u8>())(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        assert!(date("2020-01-15T10:30:00+01:00").to_string() == "2020-01-15T10:30:00+01:00");
+        assert!(date("2020:01:15 10:30:00").to_string() == "2020-01-15T10:30:00");
+        assert!(date("Wed, 15 Jan 2020 10:30:00 +0100").to_string() == "2020-01-15T10:30:00+01:00");
+        assert!(date("15 Jan 99 10:30 GMT").to_string() == "1999-01-15T10:30Z");
+        assert!(date("Wed, 15 Jan 2020 10:30:00 -0000").offset().is_none());
+        assert!(date("2020:01:  :  :  ").to_string() == "2020-01");
+        assert!(date("2020-01-15 10:30:00.25Z").to_string() == "2020-01-15T10:30:00Z");
+        assert!(date("+12020-01").year() == Some(12020));
+    }
+
+    #[test]
+    fn invalid_dates() {
+        for s in ["2020-13", "2021-02-29", "2020-01-15T24:00", "2020-01-15T10:60", "2020-01-15T10:30:61", "2020-01-15+01:00", "15 Foo 2020 10:30 +0000", "yesterday", ""] {
+            assert!(s.parse::<DateTime>().is_err(), "{s}");
+        }
+        assert!(date("2020-02-29").day() == Some(29));
+        assert!(DateTime::new(Some(2020), None, Some(1), None, None, None).is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in ["2020", "2020-01", "2020-01-15", "2020-01-15T10", "2020-01-15T10:30", "2020-01-15T10:30:60", "2020-01-15T10:30:00Z", "2020-01-15T10:30-05:45", "-0044-03-15", "+12020-01-01"] {
+            assert!(date(s).to_string() == s, "{s}");
+            assert!(date(&date(s).to_string()) == date(s), "{s}");
+        }
+        let now = DateTime::from_system_time(UNIX_EPOCH + std::time::Duration::from_secs(1_579_084_200));
+        assert!(now.to_string() == "2020-01-15T10:30:00Z");
+        assert!(DateTime::from_system_time(UNIX_EPOCH - std::time::Duration::from_secs(1)).to_string() == "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(date("2020") < date("2020-01") && date("2020-01") < date("2020-01-01"));
+        assert!(date("2019-12-31T23:59") < date("2020-01-01T00:00"));
+        // The same instant in two zones compares by the instant, then by the offset.
+        assert!(date("2020-01-15T10:30+01:00").cmp_prefix(&date("2020-01-15T09:30Z")) == Ordering::Equal);
+        assert!(date("2020-01-15T10:30+01:00") < date("2020-01-15T10:00Z"));
+        assert!(date("2020-01-01T00:30+01:00") < date("2020-01-01T00:00Z"));
+        assert!(date("2020-01-15").cmp_prefix(&date("2020-01")) == Ordering::Equal);
+        assert!(date("2020-01-15").cmp_prefix(&date("2020-02")) == Ordering::Less);
+    }
+
+    #[test]
+    fn offsets_out_of_range_are_rejected() {
+        assert!(DateTime::parse_utc_offset("+01:30") == Some(90));
+        assert!(DateTime::parse_utc_offset("-0545") == Some(-345));
+        assert!(DateTime::parse_utc_offset("+01:99").is_none());
+        assert!(DateTime::parse_utc_offset("+24:00").is_none());
+        assert!(DateTime::parse_iso8601("2020-01-15T10:30+01:99").is_none());
+        assert!(DateTime::parse_rfc2822("Wed, 15 Jan 2020 10:30:00 +0160").is_none());
+    }
+}
@@ -1,37 +1,102 @@
 use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
-use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::de::Error;
+use sha2::{Digest, Sha256};
 
 pub mod builders;
+mod datetime;
 
+pub use datetime::DateTime;
+
+// Identifies an object by the SHA-256 hash of its data.
+#[derive(Eq, Hash, PartialEq, Clone, Copy)]
+pub struct ObjectId([u8; 32]);
+
+impl ObjectId {
+    pub fn of(data: &[u8]) -> ObjectId {
+        ObjectId(Sha256::digest(data).into())
+    }
+
+    pub fn from_bytes(hash: [u8; 32]) -> ObjectId {
+        ObjectId(hash)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Display for ObjectId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(hex::encode(self.0).as_str())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut id = [0u8; 32];
+        hex::decode_to_slice(s, &mut id)?;
+        Ok(ObjectId(id))
+    }
+}
+
+impl Serialize for ObjectId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+// The data is usually right there, but objects in a library only know where to find it, see
+// store::ObjectHandle.
 #[derive(Serialize, Deserialize, Clone)]
-pub struct Object {
-    pub data: Vec<u8>,
+pub struct Object<D = Vec<u8>> {
+    pub id: ObjectId,
+    pub data: D,
     pub tags: HashSet<Tag>,
     pub form: Form,
 }
 
-impl PartialEq for Object {
+impl<D> PartialEq for Object<D> {
     fn eq(&self, other: &Self) -> bool {
-        self.data == other.data
+        self.id == other.id
     }
 }
 
-impl Eq for Object {}
+impl<D> Eq for Object<D> {}
 
-impl Hash for Object {
+impl<D> Hash for Object<D> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.data.hash(state);
+        self.id.hash(state);
     }
 }
 
+// What happens to the tags of an object that is imported again.
+#[derive(Eq, PartialEq, Clone, Copy, Default)]
+pub enum MergePolicy {
+    #[default]
+    Union, // Keep the tags of both imports, the stored ones where they conflict.
+    KeepExisting, // Keep the tags already stored, ignore the new ones.
+    KeepIncoming, // Replace the stored tags with the new ones.
+}
+
 #[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Clone)]
 pub enum Tag {
     Category(String),
     Exif {
         tag: String,
         value: String,
+        ifd: u16, // 0 for the primary image, 1 for its thumbnail.
     },
     Title(String),
     Author(String),
@@ -39,6 +104,15 @@ pub enum Tag {
         value: DateTime,
         concerns: DateConcerns,
     },
+    IdentifiedBy {
+        identifier: String,
+        confidence: u8, // Percent.
+    },
+    Mime {
+        mime: MimeType,
+        extension: Option<String>,
+    },
+    Location(Location),
     OtherUnknown {
         tag: String,
         value: String,
@@ -49,15 +123,39 @@ impl Display for Tag {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
             Self::Category(s) => f.write_fmt(format_args!("Category | {s}")),
-            Self::Exif {tag, value} => f.write_fmt(format_args!("EXIF {tag} | {value}")),
+            Self::Exif {tag, value, ifd: 0} => f.write_fmt(format_args!("EXIF {tag} | {value}")),
+            Self::Exif {tag, value, ifd: 1} => f.write_fmt(format_args!("EXIF {tag} (thumbnail) | {value}")),
+            Self::Exif {tag, value, ifd} => f.write_fmt(format_args!("EXIF {tag} (IFD {ifd}) | {value}")),
             Self::Title(s) => f.write_fmt(format_args!("Title | {s}")),
             Self::Author(s) => f.write_fmt(format_args!("Author | {s}")),
             Self::Date{value, concerns} => f.write_fmt(format_args!("Date {concerns} | {value}")),
+            Self::IdentifiedBy{identifier, confidence} => f.write_fmt(format_args!("Identified by | {identifier} ({confidence}%)")),
+            Self::Mime{mime, extension: Some(extension)} => f.write_fmt(format_args!("MIME | {mime} (.{extension})")),
+            Self::Mime{mime, extension: None} => f.write_fmt(format_args!("MIME | {mime}")),
+            Self::Location(location) => f.write_fmt(format_args!("Location | {location}")),
             Self::OtherUnknown{tag, value} => f.write_fmt(format_args!("{tag} | {value}")),
         }
     }
 }
 
+impl Tag {
+    // Whether an object can't have both tags: it has one title, one MIME type, one location and one
+    // date for each concern.
+    pub fn conflicts_with(&self, other: &Tag) -> bool {
+        match (self, other) {
+            (Tag::Title(_), Tag::Title(_)) | (Tag::Mime {..}, Tag::Mime {..}) | (Tag::Location(_), Tag::Location(_)) => self != other,
+            (Tag::Date {concerns: a, ..}, Tag::Date {concerns: b, ..}) => a == b && self != other,
+            _ => false,
+        }
+    }
+
+    // Whether a tag is about what an object shows rather than about its data, so that it applies
+    // to a copy of it in another format too.
+    pub fn is_user_tag(&self) -> bool {
+        matches!(self, Tag::Title(_) | Tag::Author(_) | Tag::Category(_) | Tag::Location(_) | Tag::Date {concerns: DateConcerns::Created, ..})
+    }
+}
+
 #[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Clone)]
 pub enum DateConcerns {
     Created, // When an object was created.
@@ -92,86 +190,318 @@ pub enum Form {
     OtherUnknown(String),
 }
 
+impl Display for Form {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::Empty => f.write_str("Empty"),
+            Self::PlainText | Self::TypesetText => f.write_str("Text"),
+            Self::Binary => f.write_str("Binary"),
+            Self::App => f.write_str("App"),
+            Self::Photo => f.write_str("Photo"),
+            Self::Sound => f.write_str("Sound"),
+            Self::Video => f.write_str("Video"),
+            Self::Model3D => f.write_str("3D model"),
+            Self::Archive => f.write_str("Archive"),
+            Self::OtherUnknown(s) => f.write_str(s.as_str()),
+        }
+    }
+}
+
 #[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Clone)]
-pub struct DateTime {
-    year: Option<i32>,
-    month: Option<u8>,
-    day: Option<u8>,
-    hour: Option<u8>,
-    minute: Option<u8>,
-    second: Option<u8>,
+pub struct MimeType {
+    pub kind: String,
+    pub subtype: String,
 }
 
-impl Display for DateTime {
+impl MimeType {
+    pub fn new(kind: &str, subtype: &str) -> MimeType {
+        MimeType {kind: kind.into(), subtype: subtype.into()}
+    }
+}
+
+// File extensions and short names of the MIME types that the built in identifiers produce.
+const MIME_TYPES: [(&str, Option<&str>, &str); 38] = [
+    ("image/tiff", Some("tif"), "TIFF"),
+    ("image/png", Some("png"), "PNG"),
+    ("image/jpeg", Some("jpg"), "JPEG"),
+    ("image/gif", Some("gif"), "GIF"),
+    ("image/webp", Some("webp"), "WebP"),
+    ("image/bmp", Some("bmp"), "BMP"),
+    ("image/heif", Some("heic"), "HEIF"),
+    ("image/avif", Some("avif"), "AVIF"),
+    ("audio/wav", Some("wav"), "WAV"),
+    ("audio/flac", Some("flac"), "FLAC"),
+    ("audio/ogg", Some("ogg"), "Ogg"),
+    ("audio/mpeg", Some("mp3"), "MP3"),
+    ("audio/mp4", Some("m4a"), "MPEG-4"),
+    ("video/mp4", Some("mp4"), "MPEG-4"),
+    ("video/x-matroska", Some("mkv"), "Matroska"),
+    ("video/x-msvideo", Some("avi"), "AVI"),
+    ("application/zip", Some("zip"), "ZIP"),
+    ("application/gzip", Some("gz"), "gzip"),
+    ("application/x-7z-compressed", Some("7z"), "7-Zip"),
+    ("application/x-tar", Some("tar"), "tar"),
+    ("model/gltf-binary", Some("glb"), "glTF"),
+    ("model/gltf+json", Some("gltf"), "glTF"),
+    ("model/x-ply", Some("ply"), "PLY"),
+    ("model/stl", Some("stl"), "STL"),
+    ("model/obj", Some("obj"), "OBJ"),
+    ("application/x-elf", None, "ELF"),
+    ("application/vnd.microsoft.portable-executable", Some("exe"), "PE"),
+    ("application/x-mach-binary", None, "Mach-O"),
+    ("application/wasm", Some("wasm"), "WebAssembly"),
+    ("application/java-archive", Some("jar"), "Java"),
+    ("application/vnd.android.package-archive", Some("apk"), "Android"),
+    ("application/pdf", Some("pdf"), "PDF"),
+    ("application/postscript", Some("ps"), "PostScript"),
+    ("application/x-dvi", Some("dvi"), "DVI"),
+    ("application/epub+zip", Some("epub"), "EPUB"),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", Some("docx"), "Word"),
+    ("text/plain", Some("txt"), "Plain"),
+    ("application/json", Some("json"), "JSON"),
+];
+
+impl MimeType {
+    pub fn parse(s: &str) -> Option<MimeType> {
+        let (kind, subtype) = s.split_once('/')?;
+        Some(MimeType::new(kind, subtype))
+    }
+
+    fn known(&self) -> Option<(Option<&'static str>, &'static str)> {
+        let name = self.to_string();
+        MIME_TYPES.iter().find(|(mime, _, _)| *mime == name).map(|(_, extension, label)| (*extension, *label))
+    }
+
+    pub fn extension(&self) -> Option<&'static str> {
+        self.known().and_then(|(extension, _)| extension)
+    }
+
+    // A short name for the format, e.g. "TIFF" for image/tiff.
+    pub fn label(&self) -> Option<&'static str> {
+        self.known().map(|(_, label)| label)
+    }
+}
+
+impl Display for MimeType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{}-{}-{} {}:{}:{}",
-                                 if let Some(v) = self.year {
-                                     v.to_string()
-                                 } else {
-                                     "####".into()
-                                 },
-                                 if let Some(v) = self.month {
-                                     v.to_string()
-                                 } else {
-                                     "##".into()
-                                 },
-                                 if let Some(v) = self.day {
-                                     v.to_string()
-                                 } else {
-                                     "##".into()
-                                 },
-                                 if let Some(v) = self.hour {
-                                     v.to_string()
-                                 } else {
-                                     "##".into()
-                                 },
-                                 if let Some(v) = self.minute {
-                                     v.to_string()
-                                 } else {
-                                     "##".into()
-                                 },
-                                 if let Some(v) = self.second {
-                                     v.to_string()
-                                 } else {
-                                     "##".into()
-                                 },
-        ))
+        f.write_fmt(format_args!("{}/{}", self.kind, self.subtype))
     }
 }
 
+// Where an object was made. Latitude and longitude are kept in 10^-7 degrees, about a
+// centimetre, so locations can be hashed and compared exactly.
 #[derive(Eq, Hash, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Location {
     place: Option<String>,
-    lat_long: (u64, u64), // *10000
+    latitude: i32, // North is positive.
+    longitude: i32, // East is positive.
+    altitude: Option<i32>, // Millimetres above sea level.
 }
 
-impl Object {
-    pub fn search(&self, query: String) -> bool {
-        match self.form {
-            Form::Empty => false,
-            Form::Binary => {
-                hex::encode(&self.data.as_slice()).contains(&query)
-            }
-            Form::Photo => {
-                for tag in &self.tags {
-                    match tag {
-                        Tag::Exif {value,..} => {
-                            if value.contains(&query) {
-                                return true;
-                            }
-                        },
-                        _ => {
-                            return false;
-                        }
-                    };
-                }
-                if self.tags.is_empty() && query.is_empty() {
-                    return true;
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(place) = &self.place {
+            write!(f, "{place}, ")?;
+        }
+        let (latitude, longitude) = (self.latitude(), self.longitude());
+        write!(f, "{:.6}° {}, {:.6}° {}", latitude.abs(), if latitude < 0.0 {'S'} else {'N'}, longitude.abs(), if longitude < 0.0 {'W'} else {'E'})?;
+        if let Some(altitude) = self.altitude() {
+            write!(f, ", {altitude:.1} m")?;
+        }
+        Ok(())
+    }
+}
+
+impl Location {
+    // Returns None for coordinates outside of ±90° latitude and ±180° longitude.
+    pub fn new(latitude: f64, longitude: f64, altitude: Option<f64>) -> Option<Location> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+        Some(Location {
+            place: None,
+            latitude: (latitude * 1e7).round() as i32,
+            longitude: (longitude * 1e7).round() as i32,
+            altitude: altitude.filter(|altitude| altitude.is_finite()).map(|altitude| (altitude * 1e3).round() as i32),
+        })
+    }
+
+    pub fn with_place(self, place: Option<String>) -> Location {
+        Location {place, ..self}
+    }
+
+    pub fn place(&self) -> Option<&str> {
+        self.place.as_deref()
+    }
+
+    pub fn latitude(&self) -> f64 {
+        f64::from(self.latitude) / 1e7
+    }
+
+    pub fn longitude(&self) -> f64 {
+        f64::from(self.longitude) / 1e7
+    }
+
+    pub fn altitude(&self) -> Option<f64> {
+        self.altitude.map(|altitude| f64::from(altitude) / 1e3)
+    }
+}
+
+impl<D> Object<D> {
+    pub fn merge_tags(&mut self, incoming: HashSet<Tag>, policy: MergePolicy) {
+        match policy {
+            MergePolicy::Union => {
+                for tag in incoming {
+                    self.add_tag(tag).ok();
                 }
-                false
             }
-            Form::PlainText => String::from_utf8_lossy(self.data.as_slice()).contains(&query),
-            _ => false
+            MergePolicy::KeepExisting => {}
+            MergePolicy::KeepIncoming => self.tags = incoming,
+        }
+    }
+
+    pub fn conflicting_tag(&self, tag: &Tag) -> Option<&Tag> {
+        self.tags.iter().find(|existing| existing.conflicts_with(tag))
+    }
+
+    // Adds a tag, unless the object already has one that it conflicts with.
+    pub fn add_tag(&mut self, tag: Tag) -> Result<(), String> {
+        if let Some(existing) = self.conflicting_tag(&tag) {
+            return Err(format!("already tagged {existing}"));
+        }
+        self.tags.insert(tag);
+        Ok(())
+    }
+
+    pub fn remove_tag(&mut self, tag: &Tag) -> bool {
+        self.tags.remove(tag)
+    }
+
+    // Replaces a tag with another, e.g. to change the title. Nothing changes if that fails.
+    pub fn replace_tag(&mut self, old: &Tag, new: Tag) -> Result<(), String> {
+        if !self.tags.remove(old) {
+            return Err(format!("not tagged {old}"));
+        }
+        self.add_tag(new).inspect_err(|_| {
+            self.tags.insert(old.clone());
+        })
+    }
+
+    pub fn mime(&self) -> Option<&MimeType> {
+        self.tags.iter().find_map(|tag| match tag {
+            Tag::Mime {mime, ..} => Some(mime),
+            _ => None,
+        })
+    }
+
+    // The file extension for the data, falling back to one for the form if the format is unknown.
+    pub fn extension(&self) -> &str {
+        let extension = self.tags.iter().find_map(|tag| match tag {
+            Tag::Mime {extension: Some(extension), ..} => Some(extension.as_str()),
+            _ => None,
+        });
+        extension.unwrap_or(match self.form {
+            Form::PlainText => "txt",
+            _ => "bin",
+        })
+    }
+
+    // The form along with the concrete format, e.g. "Photo (TIFF)".
+    pub fn describe(&self) -> String {
+        let format = self.mime().and_then(MimeType::label).or(match self.form {
+            Form::PlainText => Some("Plain"),
+            Form::TypesetText => Some("Typeset"),
+            Form::Binary => Some("Unknown format"),
+            _ => None,
+        });
+        match format {
+            Some(format) => format!("{} ({format})", self.form),
+            None => self.form.to_string(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str, concerns: DateConcerns) -> Tag {
+        Tag::Date {value: value.parse::<DateTime>().ok().unwrap(), concerns}
+    }
+
+    fn location(latitude: f64, longitude: f64) -> Tag {
+        Tag::Location(Location::new(latitude, longitude, None).unwrap())
+    }
+
+    fn object(tags: impl IntoIterator<Item = Tag>) -> Object<()> {
+        Object {id: ObjectId::of(b""), data: (), tags: tags.into_iter().collect(), form: Form::Photo}
+    }
+
+    #[test]
+    fn conflicts() {
+        let title = Tag::Title("Moon".into());
+        assert!(title.conflicts_with(&Tag::Title("Sun".into())));
+        assert!(!title.conflicts_with(&title));
+        assert!(!title.conflicts_with(&Tag::Author("Moon".into())));
+        assert!(!Tag::Author("Jane".into()).conflicts_with(&Tag::Author("John".into())));
+        assert!(!Tag::Category("a".into()).conflicts_with(&Tag::Category("b".into())));
+        let jpeg = Tag::Mime {mime: MimeType::new("image", "jpeg"), extension: Some("jpg".into())};
+        assert!(jpeg.conflicts_with(&Tag::Mime {mime: MimeType::new("image", "jpeg"), extension: Some("jpeg".into())}));
+        assert!(location(1.0, 2.0).conflicts_with(&location(1.0, 2.5)));
+        assert!(!location(1.0, 2.0).conflicts_with(&location(1.0, 2.0)));
+        // One date for each concern.
+        let created = date("2020-01-15", DateConcerns::Created);
+        assert!(created.conflicts_with(&date("2020-01-16", DateConcerns::Created)));
+        assert!(!created.conflicts_with(&date("2020-01-16", DateConcerns::Edited)));
+        assert!(!created.conflicts_with(&created));
+        let other = |concerns: &str| date("2020", DateConcerns::OtherUnknown(concerns.into()));
+        assert!(other("Printed").conflicts_with(&date("2021", DateConcerns::OtherUnknown("Printed".into()))));
+        assert!(!other("Printed").conflicts_with(&date("2021", DateConcerns::OtherUnknown("Scanned".into()))));
+    }
+
+    #[test]
+    fn adding_tags() {
+        let mut object = object([Tag::Title("Moon".into()), date("2020", DateConcerns::Created)]);
+        assert!(object.add_tag(Tag::Title("Sun".into())).is_err());
+        assert!(object.add_tag(date("2021", DateConcerns::Created)).is_err());
+        assert!(object.tags.len() == 2);
+        assert!(object.add_tag(Tag::Title("Moon".into())).is_ok());
+        assert!(object.add_tag(date("2021", DateConcerns::Edited)).is_ok());
+        assert!(object.add_tag(Tag::Category("Sky".into())).is_ok());
+        assert!(object.tags.len() == 4);
+        assert!(object.remove_tag(&Tag::Title("Moon".into())) && !object.remove_tag(&Tag::Title("Moon".into())));
+        assert!(object.add_tag(Tag::Title("Sun".into())).is_ok());
+        assert!(object.tags.contains(&Tag::Title("Sun".into())));
+    }
+
+    #[test]
+    fn replacing_tags() {
+        let (moon, sun) = (Tag::Title("Moon".into()), Tag::Title("Sun".into()));
+        let created = date("2020", DateConcerns::Created);
+        let mut object = object([moon.clone(), created.clone(), Tag::Category("Sky".into())]);
+        assert!(object.replace_tag(&moon, sun.clone()).is_ok());
+        assert!(object.tags.contains(&sun) && !object.tags.contains(&moon));
+        assert!(object.replace_tag(&moon, Tag::Title("Stars".into())).is_err());
+        // A replacement that conflicts with another tag leaves the old one in place.
+        let before = object.tags.clone();
+        assert!(object.replace_tag(&Tag::Category("Sky".into()), date("2021", DateConcerns::Created)).is_err());
+        assert!(object.tags == before);
+        assert!(object.replace_tag(&created, date("2021", DateConcerns::Created)).is_ok());
+        assert!(object.tags.contains(&date("2021", DateConcerns::Created)) && !object.tags.contains(&created));
+    }
+
+    #[test]
+    fn merging_tags() {
+        let existing = [Tag::Title("Moon".into()), Tag::Category("Sky".into())];
+        let incoming = HashSet::from([Tag::Title("Sun".into()), Tag::Category("Day".into())]);
+        let mut union = object(existing.clone());
+        union.merge_tags(incoming.clone(), MergePolicy::Union);
+        assert!(union.tags == HashSet::from([Tag::Title("Moon".into()), Tag::Category("Sky".into()), Tag::Category("Day".into())]));
+        let mut kept = object(existing.clone());
+        kept.merge_tags(incoming.clone(), MergePolicy::KeepExisting);
+        assert!(kept.tags == HashSet::from(existing));
+        let mut replaced = object([]);
+        replaced.merge_tags(incoming.clone(), MergePolicy::KeepIncoming);
+        assert!(replaced.tags == incoming);
+    }
+}
@@ -0,0 +1,430 @@
+use nom::branch::alt;
+use nom::bytes::complete::{escaped_transform, is_not, tag, tag_no_case, take_while1};
+use nom::character::complete::{char, multispace0, multispace1, none_of};
+use nom::combinator::{all_consuming, cut, eof, map, opt, peek, value, verify};
+use nom::error::{Error, ErrorKind};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::IResult;
+use crate::object::{DateConcerns, DateTime, Form, Location, Tag};
+
+// A parsed search query, e.g. `form:photo AND (author:"Jane" OR NOT category:travel)`.
+//
+// Terms are either bare words, which find objects with words starting with them in a tag value
+// or the start of a plain text, or `field op value`, where op is one of `:` (equals), `:~`
+// (contains), `<`, `<=`, `>` and `>=`. Queries are answered by index::Index. Terms next to each
+// other are ANDed together. Locations are searched with
+// `near:latitude,longitude,radius`, where the radius is in m, km or mi, and
+// `within:south,west,north,east`.
+#[derive(Clone, PartialEq)]
+pub enum Query {
+    All,
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Term {
+    Text(String),
+    Form(Form),
+    Field {
+        field: Field,
+        op: Op,
+        value: String,
+    },
+    Area(Area),
+}
+
+// A part of the earth's surface that locations are searched in. Coordinates are in degrees.
+#[derive(Clone, PartialEq)]
+pub enum Area {
+    Circle {
+        latitude: f64,
+        longitude: f64,
+        radius: f64, // Metres.
+    },
+    // A box that crosses the antimeridian has its west edge east of its east edge.
+    Box {
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+    },
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Field {
+    Title,
+    Author,
+    Category,
+    Identifier,
+    Mime,
+    Extension,
+    Exif(String),
+    Date(Option<DateConcerns>), // None matches a date of any concern.
+    Place,
+    Other(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Is,
+    Contains,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, String> {
+        if input.trim().is_empty() {
+            return Ok(Query::All);
+        }
+        match all_consuming(delimited(multispace0, or_expr, multispace0))(input) {
+            Ok((_, query)) => Ok(query),
+            // Only areas fail outright, see `area`.
+            Err(nom::Err::Failure(e)) => {
+                let term = e.input.split_whitespace().next().unwrap_or_default();
+                Err(format!("\"{}\" is not an area, write near:latitude,longitude,radius or within:south,west,north,east", truncate(term, 48)))
+            }
+            Err(nom::Err::Error(e)) => {
+                Err(format!("Unexpected input at \"{}\"", truncate(e.input, 16)))
+            }
+            Err(nom::Err::Incomplete(_)) => Err("Incomplete query".into()),
+        }
+    }
+}
+
+impl Area {
+    pub fn contains(&self, location: &Location) -> bool {
+        let (latitude, longitude) = (location.latitude(), location.longitude());
+        match *self {
+            Area::Circle {latitude: lat, longitude: long, radius} => distance((lat, long), (latitude, longitude)) <= radius,
+            Area::Box {south, west, north, east} => {
+                (south..=north).contains(&latitude) && if west <= east {
+                    (west..=east).contains(&longitude)
+                } else {
+                    longitude >= west || longitude <= east
+                }
+            }
+        }
+    }
+}
+
+// The great-circle distance in metres between two points given in degrees.
+pub fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    const EARTH_RADIUS: f64 = 6_371_008.8;
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let (d_lat, d_long) = ((b.0 - a.0).to_radians(), (b.1 - a.1).to_radians());
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_long / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+pub fn form_named(name: &str) -> Form {
+    match name.to_lowercase().as_str() {
+        "empty" => Form::Empty,
+        "text" | "plaintext" => Form::PlainText,
+        "typeset" | "typesettext" => Form::TypesetText,
+        "binary" => Form::Binary,
+        "app" => Form::App,
+        "photo" => Form::Photo,
+        "sound" => Form::Sound,
+        "video" => Form::Video,
+        "model" | "model3d" => Form::Model3D,
+        "archive" => Form::Archive,
+        _ => Form::OtherUnknown(name.to_string()),
+    }
+}
+
+pub fn form_name(form: &Form) -> String {
+    match form {
+        Form::Empty => "empty".into(),
+        Form::PlainText => "text".into(),
+        Form::TypesetText => "typeset".into(),
+        Form::Binary => "binary".into(),
+        Form::App => "app".into(),
+        Form::Photo => "photo".into(),
+        Form::Sound => "sound".into(),
+        Form::Video => "video".into(),
+        Form::Model3D => "model".into(),
+        Form::Archive => "archive".into(),
+        Form::OtherUnknown(name) => name.clone(),
+    }
+}
+
+pub fn concerns_named(name: &str) -> DateConcerns {
+    match name.to_lowercase().as_str() {
+        "created" => DateConcerns::Created,
+        "added" => DateConcerns::Added,
+        "edited" => DateConcerns::Edited,
+        _ => DateConcerns::OtherUnknown(name.to_string()),
+    }
+}
+
+// Makes a tag from its kind, which is title, author, category, location, date.<concerns>,
+// exif.<tag> or the name of any other tag, and its value as it would be written in a query.
+pub fn parse_tag(kind: &str, value: &str) -> Result<Tag, String> {
+    let lower = kind.to_lowercase();
+    Ok(match lower.as_str() {
+        "title" => Tag::Title(value.into()),
+        "author" => Tag::Author(value.into()),
+        "category" => Tag::Category(value.into()),
+        "location" => Tag::Location(parse_location(value).ok_or(format!("{value} is not a location"))?),
+        _ if lower.starts_with("date.") => Tag::Date {
+            value: parse_date(value).ok_or(format!("{value} is not a date"))?,
+            concerns: concerns_named(&kind[5..]),
+        },
+        _ if lower.starts_with("exif.") => Tag::Exif {tag: kind[5..].into(), value: value.into(), ifd: 0},
+        _ => Tag::OtherUnknown {tag: kind.into(), value: value.into()},
+    })
+}
+
+// Locations are written latitude,longitude[,altitude][,place], e.g. "48.8584,2.2945,35m,Eiffel Tower".
+pub fn parse_location(input: &str) -> Option<Location> {
+    let mut parts = input.splitn(3, ',').map(str::trim);
+    let latitude = parts.next()?.parse().ok()?;
+    let longitude = parts.next()?.parse().ok()?;
+    let rest = parts.next().unwrap_or_default();
+    let (first, after) = rest.split_once(',').map_or((rest, ""), |(first, after)| (first.trim(), after.trim()));
+    let (altitude, place) = match first.trim_end_matches('m').parse::<f64>() {
+        Ok(altitude) => (Some(altitude), after),
+        Err(_) => (None, rest),
+    };
+    let place = (!place.is_empty()).then(|| place.to_string());
+    Location::new(latitude, longitude, altitude).map(|location| location.with_place(place))
+}
+
+// near:latitude,longitude,radius with the radius in m, km or mi, or within:south,west,north,east.
+fn parse_area(key: &str, value: &str) -> Option<Area> {
+    let numbers = |s: &str| s.split(',').map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite())).collect::<Option<Vec<_>>>();
+    match key {
+        "near" => {
+            let (point, radius) = value.rsplit_once(',')?;
+            let radius = radius.trim();
+            let (radius, unit) = radius.split_at(radius.find(char::is_alphabetic).unwrap_or(radius.len()));
+            let scale = match unit.to_lowercase().as_str() {
+                "m" => 1.0,
+                "km" | "" => 1000.0,
+                "mi" => 1609.344,
+                _ => return None,
+            };
+            match numbers(point)?.as_slice() {
+                [latitude, longitude] => Some(Area::Circle {latitude: *latitude, longitude: *longitude, radius: radius.parse::<f64>().ok()? * scale}),
+                _ => None,
+            }
+        }
+        "within" => match numbers(value)?.as_slice() {
+            [south, west, north, east] if south <= north => Some(Area::Box {south: *south, west: *west, north: *north, east: *east}),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn tag_value(tag: &Tag) -> String {
+    match tag {
+        Tag::Category(s) | Tag::Title(s) | Tag::Author(s) => s.clone(),
+        Tag::Exif {value, ..} | Tag::OtherUnknown {value, ..} => value.clone(),
+        Tag::Date {value, ..} => value.to_string(),
+        Tag::IdentifiedBy {identifier, ..} => identifier.clone(),
+        Tag::Mime {mime, ..} => mime.to_string(),
+        Tag::Location(location) => location.to_string(),
+    }
+}
+
+fn truncate(s: &str, to: usize) -> String {
+    s.chars().take(to).collect()
+}
+
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag(word), peek(alt((multispace1, tag("("), tag(")"), eof))))
+}
+
+fn or_expr(input: &str) -> IResult<&str, Query> {
+    let (input, first) = and_expr(input)?;
+    let (input, rest) = many0(preceded(delimited(multispace0, keyword("OR"), multispace0), and_expr))(input)?;
+    Ok((input, rest.into_iter().fold(first, |a, b| Query::Or(Box::new(a), Box::new(b)))))
+}
+
+fn and_expr(input: &str) -> IResult<&str, Query> {
+    let (input, first) = unary(input)?;
+    let (input, rest) = many0(preceded(alt((delimited(multispace0, keyword("AND"), multispace0), multispace1)), unary))(input)?;
+    Ok((input, rest.into_iter().fold(first, |a, b| Query::And(Box::new(a), Box::new(b)))))
+}
+
+fn unary(input: &str) -> IResult<&str, Query> {
+    alt((
+        map(preceded(pair(keyword("NOT"), multispace0), unary), |q| Query::Not(Box::new(q))),
+        delimited(pair(char('('), multispace0), or_expr, pair(multispace0, char(')'))),
+        map(term, Query::Term),
+    ))(input)
+}
+
+fn term(input: &str) -> IResult<&str, Term> {
+    alt((
+        area,
+        map(tuple((key, op, text)), |(key, op, value)| field_term(key, op, value)),
+        map(text, Term::Text),
+    ))(input)
+}
+
+// near: and within: terms. One whose area can't be read fails the whole query rather than being
+// searched for as text, which would quietly find nothing.
+fn area(input: &str) -> IResult<&str, Term> {
+    let (rest, key) = terminated(alt((tag_no_case("near"), tag_no_case("within"))), alt((char(':'), char('='))))(input)?;
+    let (rest, value) = cut(text)(rest)?;
+    match parse_area(key.to_lowercase().as_str(), value.as_str()) {
+        Some(area) => Ok((rest, Term::Area(area))),
+        None => Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify))),
+    }
+}
+
+fn field_term(key: &str, op: Op, value: String) -> Term {
+    let lower = key.to_lowercase();
+    let field = match lower.as_str() {
+        "form" => return Term::Form(form_named(value.as_str())),
+        "place" => Field::Place,
+        "title" => Field::Title,
+        "author" => Field::Author,
+        "category" => Field::Category,
+        "identifier" => Field::Identifier,
+        "mime" => Field::Mime,
+        "ext" | "extension" => Field::Extension,
+        "date" => Field::Date(None),
+        _ if lower.starts_with("date.") => Field::Date(Some(concerns_named(&key[5..]))),
+        _ if lower.starts_with("exif.") => Field::Exif(key[5..].to_string()),
+        _ => Field::Other(key.to_string()),
+    };
+    Term::Field {field, op, value}
+}
+
+fn key(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '.' || c == '_')(input)
+}
+
+fn op(input: &str) -> IResult<&str, Op> {
+    alt((
+        value(Op::Contains, tag(":~")),
+        value(Op::Ge, tag(">=")),
+        value(Op::Le, tag("<=")),
+        value(Op::Gt, tag(">")),
+        value(Op::Lt, tag("<")),
+        value(Op::Is, tag(":")),
+        value(Op::Is, tag("=")),
+    ))(input)
+}
+
+fn text(input: &str) -> IResult<&str, String> {
+    alt((quoted, map(verify(is_not(" \t\r\n()\""), |s: &str| !["AND", "OR", "NOT"].contains(&s)), String::from)))(input)
+}
+
+fn quoted(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        map(opt(escaped_transform(none_of("\\\""), '\\', alt((value("\\", tag("\\")), value("\"", tag("\"")))))), Option::unwrap_or_default),
+        char('"'),
+    )(input)
+}
+
+pub fn parse_date(input: &str) -> Option<DateTime> {
+    input.parse().ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: Term) -> Query {
+        Query::Term(term)
+    }
+
+    fn text(text: &str) -> Query {
+        term(Term::Text(text.into()))
+    }
+
+    fn field(field: Field, op: Op, value: &str) -> Query {
+        term(Term::Field {field, op, value: value.into()})
+    }
+
+    fn and(a: Query, b: Query) -> Query {
+        Query::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Query, b: Query) -> Query {
+        Query::Or(Box::new(a), Box::new(b))
+    }
+
+    fn not(a: Query) -> Query {
+        Query::Not(Box::new(a))
+    }
+
+    #[test]
+    fn operators() {
+        assert!(Query::parse("  ") == Ok(Query::All));
+        assert!(Query::parse("a b") == Ok(and(text("a"), text("b"))));
+        assert!(Query::parse("a AND b") == Ok(and(text("a"), text("b"))));
+        // AND binds tighter than OR, and NOT tighter than both.
+        assert!(Query::parse("a OR b c") == Ok(or(text("a"), and(text("b"), text("c")))));
+        assert!(Query::parse("a AND b OR c") == Ok(or(and(text("a"), text("b")), text("c"))));
+        assert!(Query::parse("NOT a b") == Ok(and(not(text("a")), text("b"))));
+        assert!(Query::parse("a OR b OR c") == Ok(or(or(text("a"), text("b")), text("c"))));
+        assert!(Query::parse("( a OR b ) c") == Ok(and(or(text("a"), text("b")), text("c"))));
+        assert!(Query::parse("NOT (a OR NOT b)") == Ok(not(or(text("a"), not(text("b"))))));
+        // Operators are upper case and whole words.
+        assert!(Query::parse("a or b") == Ok(and(and(text("a"), text("or")), text("b"))));
+        assert!(Query::parse("ORANGE NOTE") == Ok(and(text("ORANGE"), text("NOTE"))));
+    }
+
+    #[test]
+    fn fields() {
+        assert!(Query::parse("title:Moon") == Ok(field(Field::Title, Op::Is, "Moon")));
+        assert!(Query::parse("Author=jane") == Ok(field(Field::Author, Op::Is, "jane")));
+        assert!(Query::parse("category:~trav") == Ok(field(Field::Category, Op::Contains, "trav")));
+        assert!(Query::parse("date<2020") == Ok(field(Field::Date(None), Op::Lt, "2020")));
+        assert!(Query::parse("date.created<=2020-05") == Ok(field(Field::Date(Some(DateConcerns::Created)), Op::Le, "2020-05")));
+        assert!(Query::parse("date.Published>2001") == Ok(field(Field::Date(Some(DateConcerns::OtherUnknown("Published".into()))), Op::Gt, "2001")));
+        assert!(Query::parse("exif.Model>=D") == Ok(field(Field::Exif("Model".into()), Op::Ge, "D")));
+        assert!(Query::parse("ext:jpg mime:~image") == Ok(and(field(Field::Extension, Op::Is, "jpg"), field(Field::Mime, Op::Contains, "image"))));
+        assert!(Query::parse("rating:5") == Ok(field(Field::Other("rating".into()), Op::Is, "5")));
+        assert!(Query::parse("form:photo") == Ok(term(Term::Form(Form::Photo))));
+        assert!(Query::parse("form:Model3D") == Ok(term(Term::Form(Form::Model3D))));
+    }
+
+    #[test]
+    fn quoted_text() {
+        assert!(Query::parse("\"a OR b\"") == Ok(text("a OR b")));
+        assert!(Query::parse("title:\"The \\\"Moon\\\" \\\\ more\"") == Ok(field(Field::Title, Op::Is, "The \"Moon\" \\ more")));
+        assert!(Query::parse("author:\"\"") == Ok(field(Field::Author, Op::Is, "")));
+        assert!(Query::parse("\"(x)\" NOT \"AND\"") == Ok(and(text("(x)"), not(text("AND")))));
+    }
+
+    #[test]
+    fn errors() {
+        for query in ["(a", "a)", "a OR", "NOT", "AND b", "\"open", "a (", "()"] {
+            let error = Query::parse(query).err();
+            assert!(error.as_ref().is_some_and(|e| !e.contains("is not an area")), "{query}: {error:?}");
+        }
+    }
+
+    #[test]
+    fn areas() {
+        assert!(Query::parse("near:48.85,2.35,10km") == Ok(term(Term::Area(Area::Circle {latitude: 48.85, longitude: 2.35, radius: 10000.0}))));
+        assert!(Query::parse("NEAR=1,2,500m") == Ok(term(Term::Area(Area::Circle {latitude: 1.0, longitude: 2.0, radius: 500.0}))));
+        assert!(Query::parse("within:1,2,3,4") == Ok(term(Term::Area(Area::Box {south: 1.0, west: 2.0, north: 3.0, east: 4.0}))));
+        // Words that merely start like an area are still text.
+        assert!(Query::parse("nearby") == Ok(term(Term::Text("nearby".into()))));
+        assert!(Query::parse("near") == Ok(term(Term::Text("near".into()))));
+    }
+
+    #[test]
+    fn unreadable_areas_are_errors() {
+        for query in ["near:1,2,bad", "near:1,2", "near:1,2,3lightyears", "within:1,2,3", "within:3,0,1,0", "form:photo near:", "near:\"\""] {
+            let error = Query::parse(query).err();
+            assert!(error.as_ref().is_some_and(|e| e.contains("is not an area")), "{query}: {error:?}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use exif::Context;
+use serde_json::{json, Value};
+use crate::store::Entry;
+
+// The version of how the tags and form of an object are serialized. Whenever Tag, Form,
+// DateTime, DateConcerns, Location or MimeType change shape, bump it and add a migration from
+// the previous version.
+//   1  stores written before versions were recorded. EXIF tags may lack their IFD and dates
+//      their offset from UTC, and EXIF tags may be named by the description of the field, e.g.
+//      "Model of image input equipment" rather than "Model".
+//   2  every EXIF tag has an IFD and every date an offset, even if it is null.
+pub const SCHEMA: u16 = 2;
+
+// MIGRATIONS[0] upgrades version 1 to 2, and so on.
+const MIGRATIONS: [fn(&mut Value); SCHEMA as usize - 1] = [to_2];
+
+// Reads an entry written in `schema`, migrating it to the current version first. Versions this
+// program doesn't know have to be rejected before.
+pub fn entry(mut entry: Value, schema: u16) -> Result<Entry, String> {
+    for migration in &MIGRATIONS[schema as usize - 1..] {
+        migration(&mut entry);
+    }
+    serde_json::from_value(entry).map_err(|e| e.to_string())
+}
+
+fn to_2(entry: &mut Value) {
+    for tag in entry.get_mut("tags").and_then(Value::as_array_mut).into_iter().flatten() {
+        if let Some(Value::Object(exif)) = tag.get_mut("Exif") {
+            exif.entry("ifd").or_insert(json!(0));
+            if let Some(name) = exif.get("tag").and_then(Value::as_str).and_then(|tag| exif_names().get(tag)) {
+                exif.insert("tag".into(), json!(name));
+            }
+        }
+        if let Some(Value::Object(date)) = tag.pointer_mut("/Date/value") {
+            date.entry("offset").or_insert(Value::Null);
+        }
+    }
+}
+
+// The names of EXIF fields by their descriptions. A few fields share a description, which is
+// taken for the first of them.
+fn exif_names() -> &'static HashMap<String, String> {
+    static NAMES: OnceLock<HashMap<String, String>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let mut names = HashMap::new();
+        for context in [Context::Tiff, Context::Exif, Context::Gps, Context::Interop] {
+            for number in 0..=u16::MAX {
+                let tag = exif::Tag(context, number);
+                if let Some(description) = tag.description() {
+                    names.entry(description.to_string()).or_insert(tag.to_string());
+                }
+            }
+        }
+        names
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::object::{DateConcerns, DateTime, Form, Tag};
+    use super::*;
+
+    fn date(value: &str) -> Tag {
+        Tag::Date {value: value.parse::<DateTime>().ok().unwrap(), concerns: DateConcerns::Created}
+    }
+
+    #[test]
+    fn version_1_gets_defaults() {
+        let old = json!({
+            "tags": [
+                {"Exif": {"tag": "Model", "value": "D50"}},
+                {"Exif": {"tag": "Model", "value": "D40", "ifd": 1}},
+                {"Exif": {"tag": "Manufacturer of image input equipment", "value": "Canon"}},
+                {"Exif": {"tag": "Latitude", "value": "48 deg 51 min 30 sec"}},
+                {"Exif": {"tag": "59932", "value": "0x1c"}},
+                {"Date": {"value": {"year": 2020, "month": 1, "day": 15, "hour": null, "minute": null, "second": null}, "concerns": "Created"}},
+                {"Title": "Moon"},
+            ],
+            "form": "Photo",
+        });
+        let entry = entry(old, 1).unwrap();
+        assert!(entry.form == Form::Photo);
+        assert!(entry.tags == HashSet::from([
+            Tag::Exif {tag: "Model".into(), value: "D50".into(), ifd: 0},
+            Tag::Exif {tag: "Model".into(), value: "D40".into(), ifd: 1},
+            Tag::Exif {tag: "Make".into(), value: "Canon".into(), ifd: 0},
+            Tag::Exif {tag: "GPSLatitude".into(), value: "48 deg 51 min 30 sec".into(), ifd: 0},
+            Tag::Exif {tag: "59932".into(), value: "0x1c".into(), ifd: 0},
+            date("2020-01-15"),
+            Tag::Title("Moon".into()),
+        ]));
+        assert!(super::entry(json!({"tags": [], "form": "Binary"}), 1).is_ok_and(|entry| entry.tags.is_empty()));
+    }
+
+    #[test]
+    fn current_version_is_read_as_is() {
+        let tags = HashSet::from([Tag::Exif {tag: "Model".into(), value: "D50".into(), ifd: 1}, date("2020-01-15T10:30+01:00")]);
+        let current = serde_json::to_value(Entry {tags: tags.clone(), form: Form::Photo, words: None}).unwrap();
+        assert!(entry(current, SCHEMA).is_ok_and(|entry| entry.tags == tags));
+        // What the current version requires isn't filled in for it.
+        assert!(entry(json!({"tags": [{"Exif": {"tag": "Model", "value": "D50"}}], "form": "Photo"}), SCHEMA).is_err());
+    }
+}
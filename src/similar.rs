@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::Path;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::store::ObjectHandle;
+use crate::thumbnail::{thumbnail, SIZES};
+
+// Photos that look the same but whose data differs, like a copy that was resized or saved again
+// with other settings, have different ids. They are found by comparing perceptual hashes, which
+// change little when the picture changes little.
+
+// How many of the 64 bits of two hashes may differ for their photos to count as near duplicates.
+pub const THRESHOLD: u32 = 10;
+
+// The hash of a photo, made from its smallest thumbnail so that it is cheap once the thumbnail
+// is cached.
+pub fn perceptual_hash(store: &Path, object: &ObjectHandle) -> Result<u64> {
+    let png = thumbnail(store, object, SIZES[0])?;
+    let image = image::load_from_memory(png.as_slice()).map_err(|e| Error::Decode(e.to_string()))?;
+    Ok(difference_hash(&image))
+}
+
+// A difference hash: the image is shrunk to 9 by 8 grey pixels, and each bit says whether a
+// pixel is brighter than the one to its right.
+pub fn difference_hash(image: &DynamicImage) -> u64 {
+    let grey = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = hash << 1 | u64::from(grey.get_pixel(x, y)[0] > grey.get_pixel(x + 1, y)[0]);
+        }
+    }
+    hash
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Groups of photos that are near duplicates, each group and the groups sorted by id. Photos are
+// grouped with every photo within `threshold` of one of them, so a group can hold photos further
+// apart than that. Every pair is compared, which is fine for the size of stores so far.
+pub fn near_duplicates(hashes: &HashMap<ObjectId, u64>, threshold: u32) -> Vec<Vec<ObjectId>> {
+    let mut ids = hashes.keys().copied().collect::<Vec<_>>();
+    ids.sort_by_key(|id| id.to_string());
+    // Each photo points to another in its group, the first of a group to itself.
+    let mut parent = (0..ids.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..ids.len() {
+        for j in i + 1..ids.len() {
+            if distance(hashes[&ids[i]], hashes[&ids[j]]) <= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut groups = vec![vec![]; ids.len()];
+    for (i, id) in ids.iter().enumerate() {
+        groups[root(&mut parent, i)].push(*id);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+    use super::*;
+
+    fn image(width: u32, height: u32, brightness: impl Fn(u32, u32) -> u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| Luma([brightness(x, y)])))
+    }
+
+    #[test]
+    fn hashes() {
+        assert!(difference_hash(&image(90, 80, |x, _| x as u8)) == 0);
+        assert!(difference_hash(&image(90, 80, |x, _| 255 - x as u8)) == u64::MAX);
+        // Each row of the hash is one byte, darker to the right in the top half.
+        let halves = image(90, 80, |x, y| if y < 40 { 200 - x as u8 } else { x as u8 });
+        assert!(difference_hash(&halves) == 0xffff_ffff_0000_0000);
+        // A smaller copy hashes nearly the same, a different picture doesn't.
+        let picture = |scale: u32| image(72 * scale, 64 * scale, move |x, y| ((x / scale * 7 + y / scale * 13) % 97 * 2) as u8);
+        assert!(distance(difference_hash(&picture(4)), difference_hash(&picture(2))) <= THRESHOLD);
+        assert!(distance(difference_hash(&picture(4)), difference_hash(&halves)) > THRESHOLD);
+    }
+
+    #[test]
+    fn distances() {
+        assert!(distance(0, 0) == 0);
+        assert!(distance(0b1011, 0b0010) == 2);
+        assert!(distance(0, u64::MAX) == 64);
+    }
+
+    #[test]
+    fn groups() {
+        let ids = (0..6).map(|n: u8| ObjectId::of(&[n])).collect::<Vec<_>>();
+        let hashes = HashMap::from([
+            (ids[0], 0),
+            (ids[1], 0xff), // 8 bits from the first.
+            (ids[2], 0xffff), // 8 from the second, 16 from the first.
+            (ids[3], u64::MAX), // Far from all.
+            (ids[4], u64::MAX ^ 0x3ff), // Exactly 10 from the fourth.
+            (ids[5], 0xffff_0000_0000_0000), // Alone.
+        ]);
+        let sorted = |mut group: Vec<ObjectId>| {
+            group.sort_by_key(|id| id.to_string());
+            group
+        };
+        let mut expected = vec![sorted(ids[..3].to_vec()), sorted(ids[3..5].to_vec())];
+        expected.sort_by_key(|group| group[0].to_string());
+        assert!(near_duplicates(&hashes, 10) == expected);
+        assert!(near_duplicates(&hashes, 9) == vec![sorted(ids[..3].to_vec())]);
+        assert!(near_duplicates(&hashes, 7).is_empty());
+        assert!(near_duplicates(&HashMap::new(), THRESHOLD).is_empty());
+    }
+}
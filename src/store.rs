@@ -0,0 +1,601 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::catalog;
+use crate::error::{Error, Result};
+use crate::object::{Form, Object, ObjectId, Tag};
+use crate::schema;
+use crate::thumbnail;
+
+// On-disk layout of a store:
+//   <root>/blobs/ab/abcdef...  object data, named by the SHA-256 of its contents
+//   <root>/catalog.bin         tags and form of every object, keyed by the same hash, see catalog.rs
+//   <root>/catalog.bin.1 ...   the catalog as it was before each of the last saves, newest first
+//   <root>/thumbnails/...      previews of photos, see thumbnail.rs
+//   <root>/incoming/...        data being imported, see `receive` and `claim_incoming`
+// Every file is written aside and renamed into place, so a crash leaves either the old or the
+// new version of it, never a truncated one.
+const BLOBS: &str = "blobs";
+const CATALOG: &str = "catalog.bin";
+const JSON_CATALOG: &str = "catalog.json";
+const BACKUPS: usize = 5;
+const INCOMING: &str = "incoming";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    #[serde(serialize_with = "serialize_sorted")]
+    pub tags: HashSet<Tag>,
+    pub form: Form,
+    // The words a plain text is searched by, see index::words, so they needn't be read from its
+    // data whenever the store is opened. Missing in stores from before they were kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub words: Option<BTreeSet<String>>,
+}
+
+// Tags in the order of their own encoding, as sets read back from disk iterate in another order
+// than the sets they were written from.
+fn serialize_sorted<S: serde::Serializer>(tags: &HashSet<Tag>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let mut tags = tags.iter().collect::<Vec<_>>();
+    tags.sort_by_cached_key(|tag| {
+        let mut key = vec![];
+        let _ = ciborium::ser::into_writer(tag, &mut key);
+        key
+    });
+    serializer.collect_seq(tags)
+}
+
+// Objects as they were serialized before they carried an id. Their tags and form are schema 1.
+#[derive(Deserialize)]
+struct LegacyObject {
+    data: Vec<u8>,
+    tags: serde_json::Value,
+    form: serde_json::Value,
+}
+
+// The data of an object in a store, read only when it is needed.
+#[derive(Clone)]
+pub struct Blob {
+    path: PathBuf,
+    size: u64,
+}
+
+// An object whose tags and form are kept in memory, and its data in the store.
+pub type ObjectHandle = Object<Blob>;
+
+impl Blob {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // For streaming the data rather than reading it all at once.
+    pub fn open(&self) -> Result<File> {
+        File::open(&self.path).map_err(Error::io(&self.path))
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        fs::read(&self.path).map_err(Error::io(&self.path))
+    }
+
+    // At most the first `length` bytes, e.g. for a preview.
+    pub fn read_prefix(&self, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        self.open()?.take(length as u64).read_to_end(&mut data).map_err(Error::io(&self.path))?;
+        Ok(data)
+    }
+}
+
+impl ObjectHandle {
+    pub fn load(&self) -> Result<Object> {
+        Ok(Object {id: self.id, data: self.data.read()?, tags: self.tags.clone(), form: self.form.clone()})
+    }
+}
+
+pub struct Store {
+    root: PathBuf,
+    catalog: HashMap<ObjectId, Entry>,
+    recovered_from: Option<PathBuf>,
+    damaged: bool, // Whether the catalog has to be moved aside before it is next written.
+}
+
+impl Store {
+    pub fn open(root: &Path) -> Result<Store> {
+        // A migration that was interrupted between moving the legacy file aside and moving the
+        // new store into its place is started over.
+        if !root.exists() && with_suffix(root, ".migrating").is_dir() && with_suffix(root, ".legacy").is_file() {
+            fs::rename(with_suffix(root, ".legacy"), root).map_err(Error::io(root))?;
+        }
+        if root.is_file() {
+            return Store::migrate_legacy(root);
+        }
+        fs::create_dir_all(root.join(BLOBS)).map_err(Error::io(root))?;
+        clear_incoming(&root.join(INCOMING));
+        // Stores from before the binary catalog have a JSON one. It is read until the first flush
+        // writes a binary catalog, and left in place after that as the oldest backup.
+        let candidates = [CATALOG, JSON_CATALOG].into_iter()
+            .flat_map(|name| (0..=BACKUPS).map(move |generation| (name, generation, catalog_path(root, name, generation))))
+            .filter(|(_, _, path)| path.exists())
+            .collect::<Vec<_>>();
+        // Falls back to the newest backup that can be read. The damaged catalog is moved aside on
+        // the next flush.
+        let mut error = None;
+        for (i, (name, generation, path)) in candidates.into_iter().enumerate() {
+            match read_catalog(&path, name == JSON_CATALOG) {
+                Ok(catalog) => {
+                    let recovered = i > 0 || generation > 0;
+                    return Ok(Store {root: root.to_path_buf(), catalog, recovered_from: recovered.then_some(path), damaged: recovered});
+                }
+                // A newer program's catalog isn't damaged, and falling back to an older backup
+                // would lose what it wrote.
+                Err(e @ Error::Version {..}) => return Err(e),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(Store {root: root.to_path_buf(), catalog: HashMap::new(), recovered_from: None, damaged: false}),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The backup the catalog was recovered from when opening, if it couldn't be read itself.
+    pub fn recovered_from(&self) -> Option<&Path> {
+        self.recovered_from.as_deref()
+    }
+
+    // Stores used to be a single JSON file holding every object. Its objects are re-added to a
+    // fresh store next to it, and only once that is flushed is the file moved aside and the new
+    // store put in its place. Until then a failure leaves the file as it was.
+    fn migrate_legacy(root: &Path) -> Result<Store> {
+        let data = fs::read(root).map_err(Error::io(root))?;
+        let objects: Vec<LegacyObject> = serde_json::from_slice(data.as_slice()).map_err(Error::parse(root))?;
+        let migrating = with_suffix(root, ".migrating");
+        let _ = fs::remove_dir_all(&migrating);
+        let migrated = Store::open(&migrating).and_then(|mut store| {
+            for LegacyObject {data, tags, form} in objects {
+                let Entry {tags, form, ..} = schema::entry(serde_json::json!({"tags": tags, "form": form}), 1).map_err(Error::parse(root))?;
+                store.put(&Object {id: ObjectId::of(data.as_slice()), data, tags, form})?;
+            }
+            store.flush()?;
+            Ok(store)
+        });
+        let mut store = match migrated {
+            Ok(store) => store,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&migrating);
+                return Err(e);
+            }
+        };
+        fs::rename(root, with_suffix(root, ".legacy")).map_err(Error::io(root))?;
+        fs::rename(&migrating, root).map_err(Error::io(&migrating))?;
+        store.root = root.to_path_buf();
+        Ok(store)
+    }
+
+    fn blob_path(&self, id: &ObjectId) -> PathBuf {
+        let name = id.to_string();
+        self.root.join(BLOBS).join(&name[..2]).join(name)
+    }
+
+    // Every object in the catalog, without reading its data. Fails if any blob is missing, rather
+    // than leaving the object out: saving would then delete it.
+    pub fn handles(&self) -> Result<HashMap<ObjectId, ObjectHandle>> {
+        let mut handles = HashMap::new();
+        for (id, entry) in &self.catalog {
+            let path = self.blob_path(id);
+            let size = fs::metadata(&path).map_err(Error::io(&path))?.len();
+            handles.insert(*id, Object {id: *id, data: Blob {path, size}, tags: entry.tags.clone(), form: entry.form.clone()});
+        }
+        Ok(handles)
+    }
+
+    // Adds an object, or updates its tags and form if its data is already stored.
+    // The blob is only written the first time.
+    pub fn put(&mut self, object: &Object) -> Result<ObjectHandle> {
+        let path = self.blob_path(&object.id);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap()).map_err(Error::io(&path))?;
+            write_atomic(&path, object.data.as_slice())?;
+        }
+        self.set_entry(object);
+        let blob = Blob {path, size: object.data.len() as u64};
+        Ok(Object {id: object.id, data: blob, tags: object.tags.clone(), form: object.form.clone()})
+    }
+
+    // Like `put`, for an object whose data was copied into the store with `receive`. The incoming
+    // blob is moved into place, or deleted if the data is already stored.
+    pub fn adopt(&mut self, object: &ObjectHandle) -> Result<ObjectHandle> {
+        let path = self.blob_path(&object.id);
+        let incoming = &object.data.path;
+        if path.exists() {
+            fs::remove_file(incoming).or_else(ignore_not_found).map_err(Error::io(incoming))?;
+        } else {
+            fs::create_dir_all(path.parent().unwrap()).map_err(Error::io(&path))?;
+            fs::rename(incoming, &path).map_err(Error::io(incoming))?;
+            sync_dir(&path);
+        }
+        self.set_entry(object);
+        let blob = Blob {path, size: object.data.size};
+        Ok(Object {id: object.id, data: blob, tags: object.tags.clone(), form: object.form.clone()})
+    }
+
+    // Updates the tags and form of an object that is already stored.
+    pub fn update(&mut self, object: &ObjectHandle) {
+        self.set_entry(object);
+    }
+
+    // The words recorded for a plain text with `set_words`, if any.
+    pub fn words(&self, id: &ObjectId) -> Option<HashSet<String>> {
+        self.catalog.get(id)?.words.as_ref().map(|words| words.iter().cloned().collect())
+    }
+
+    pub fn set_words(&mut self, id: &ObjectId, words: &HashSet<String>) {
+        if let Some(entry) = self.catalog.get_mut(id) {
+            entry.words = Some(words.iter().cloned().collect());
+        }
+    }
+
+    // The words only depend on the data, so they are kept when the tags or form change.
+    fn set_entry<D>(&mut self, object: &Object<D>) {
+        let words = self.catalog.remove(&object.id).and_then(|entry| entry.words);
+        self.catalog.insert(object.id, Entry {tags: object.tags.clone(), form: object.form.clone(), words});
+    }
+
+    // Only takes the object out of the catalog. Its blob is deleted by `flush` once no backup
+    // refers to it either.
+    pub fn remove(&mut self, id: &ObjectId) -> bool {
+        self.catalog.remove(id).is_some()
+    }
+
+    // Writes the catalog, keeping the one it replaces as the newest backup. Blobs are written as
+    // they are added, so this is all that is needed to persist the store.
+    pub fn flush(&mut self) -> Result<()> {
+        let path = self.root.join(CATALOG);
+        let data = catalog::encode(&path, &self.catalog)?;
+        if self.damaged {
+            fs::rename(&path, with_suffix(&path, ".damaged")).or_else(ignore_not_found).map_err(Error::io(&path))?;
+            self.damaged = false;
+        }
+        match fs::read(&path) {
+            Ok(old) if old == data => return Ok(()),
+            Ok(old) => {
+                for generation in (1..BACKUPS).rev() {
+                    let (from, to) = (catalog_path(&self.root, CATALOG, generation), catalog_path(&self.root, CATALOG, generation + 1));
+                    fs::rename(&from, to).or_else(ignore_not_found).map_err(Error::io(&from))?;
+                }
+                write_atomic(&catalog_path(&self.root, CATALOG, 1), old.as_slice())?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::io(&path)(e)),
+        }
+        write_atomic(&path, data.as_slice())?;
+        self.collect_garbage()
+    }
+
+    // Deletes the blobs and thumbnails of objects that neither the catalog nor any backup refers
+    // to, along with files left behind by interrupted writes. JSON catalogs count as backups,
+    // since `open` falls back to them.
+    fn collect_garbage(&self) -> Result<()> {
+        let mut referenced = self.catalog.keys().map(ObjectId::to_string).collect::<HashSet<_>>();
+        let backups = (1..=BACKUPS).map(|generation| (catalog_path(&self.root, CATALOG, generation), false))
+            .chain((0..=BACKUPS).map(|generation| (catalog_path(&self.root, JSON_CATALOG, generation), true)));
+        for (path, json) in backups {
+            if let Ok(backup) = read_catalog(&path, json) {
+                referenced.extend(backup.keys().map(ObjectId::to_string));
+            }
+        }
+        let blobs = self.root.join(BLOBS);
+        for shard in fs::read_dir(&blobs).map_err(Error::io(&blobs))?.filter_map(|entry| entry.ok()) {
+            let Ok(entries) = fs::read_dir(shard.path()) else { continue };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if referenced.contains(&name) {
+                    continue;
+                }
+                fs::remove_file(entry.path()).or_else(ignore_not_found).map_err(Error::io(&entry.path()))?;
+                if let Ok(id) = name.parse() {
+                    thumbnail::remove(&self.root, &id);
+                }
+            }
+            let _ = fs::remove_dir(shard.path()); // Only succeeds once the shard is empty.
+        }
+        Ok(())
+    }
+}
+
+// Copies data into the incoming directory of the store at `root` a chunk at a time, hashing it on
+// the way, so that data larger than memory can be imported. The blob only becomes part of the
+// store once an object for it is added with `Store::adopt`. This doesn't need the store itself,
+// so it can run on any thread.
+pub fn receive(root: &Path, data: &mut impl Read) -> Result<(ObjectId, Blob)> {
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+    let dir = root.join(INCOMING);
+    fs::create_dir_all(&dir).and_then(|_| claim_incoming(&dir)).map_err(Error::io(&dir))?;
+    let path = dir.join(format!("{}-{}", process::id(), RECEIVED.fetch_add(1, Ordering::Relaxed)));
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut chunk = vec![0; 1 << 16];
+    let received = File::create(&path).and_then(|mut file| {
+        loop {
+            match data.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    hasher.update(&chunk[..n]);
+                    file.write_all(&chunk[..n])?;
+                    size += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        file.sync_all()
+    });
+    if let Err(e) = received {
+        let _ = fs::remove_file(&path);
+        return Err(Error::io(&path)(e));
+    }
+    Ok((ObjectId::from_bytes(hasher.finalize().into()), Blob {path, size}))
+}
+
+// Data is received as <pid>-<n> in the incoming directory, and <pid>.lock is kept locked by its
+// process for as long as that runs. Locks may be left behind by processes that ended, or taken
+// by other processes opening the store, so a process makes sure it holds its own.
+fn claim_incoming(dir: &Path) -> io::Result<()> {
+    static CLAIMED: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+    let mut claimed = CLAIMED.lock().unwrap_or_else(PoisonError::into_inner);
+    // Locking the same file twice would wait for ever, so it is known by one name.
+    let path = fs::canonicalize(dir)?.join(format!("{}.lock", process::id()));
+    if claimed.iter().any(|(claimed, _)| *claimed == path) && path.exists() {
+        return Ok(());
+    }
+    claimed.retain(|(claimed, _)| *claimed != path);
+    loop {
+        let lock = File::options().create(true).truncate(false).write(true).open(&path)?;
+        lock.lock()?;
+        // Another process may have taken it for left behind and removed it before it was locked.
+        if path.exists() {
+            claimed.push((path, lock));
+            return Ok(());
+        }
+    }
+}
+
+// Removes what was left in the incoming directory by imports that were interrupted, but not what
+// other processes are receiving into the store, see `claim_incoming`.
+fn clear_incoming(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let names = entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let processes = names.iter().filter_map(|name| name.split(['-', '.']).next()).collect::<HashSet<_>>();
+    for process in processes {
+        let lock_path = dir.join(format!("{process}.lock"));
+        // Held until its files are removed, so that the process can't claim it in between.
+        let lock = File::options().write(true).open(&lock_path);
+        if lock.as_ref().is_ok_and(|lock| lock.try_lock().is_err()) {
+            continue;
+        }
+        for name in names.iter().filter(|name| name.strip_prefix(process).is_some_and(|rest| rest.starts_with('-'))) {
+            let _ = fs::remove_file(dir.join(name));
+        }
+        let _ = fs::remove_file(&lock_path);
+    }
+}
+
+fn read_catalog(path: &Path, json: bool) -> Result<HashMap<ObjectId, Entry>> {
+    let data = fs::read(path).map_err(Error::io(path))?;
+    if json {
+        // JSON catalogs were never versioned, so they are schema 1.
+        let entries: HashMap<ObjectId, serde_json::Value> = serde_json::from_slice(data.as_slice()).map_err(Error::parse(path))?;
+        entries.into_iter().map(|(id, entry)| Ok((id, schema::entry(entry, 1).map_err(Error::parse(path))?))).collect()
+    } else {
+        catalog::decode(path, data.as_slice())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// The catalog itself is generation 0, its backups count up from 1.
+fn catalog_path(root: &Path, name: &str, generation: usize) -> PathBuf {
+    match generation {
+        0 => root.join(name),
+        _ => root.join(format!("{name}.{generation}")),
+    }
+}
+
+fn ignore_not_found(e: io::Error) -> io::Result<()> {
+    if e.kind() == io::ErrorKind::NotFound { Ok(()) } else { Err(e) }
+}
+
+// Writes the data next to `path`, syncs it to disk and renames it over `path`, then syncs the
+// directory so the rename itself survives a crash.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let written = File::create(&temporary)
+        .and_then(|mut file| file.write_all(data).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::io(path)(e));
+    }
+    sync_dir(path);
+    Ok(())
+}
+
+// Syncs the directory a file was just renamed into. Directories can't be opened as files
+// everywhere; where they can't, the rename has to do.
+fn sync_dir(path: &Path) {
+    if let Ok(dir) = File::open(path.parent().unwrap()) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A fresh directory for a store, removed again when dropped.
+    pub struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("object_store-test-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn object(data: &[u8]) -> Object {
+        Object {id: ObjectId::of(data), data: data.to_vec(), tags: HashSet::new(), form: Form::Binary}
+    }
+
+    #[test]
+    fn legacy_file_is_kept_until_migrated() {
+        let dir = TempDir::new("legacy");
+        fs::create_dir_all(&dir.0).unwrap();
+        let root = dir.0.join("store");
+        let legacy = |form: &str| format!(r#"[{{"data": [104, 105], "tags": [{{"Title": "hi"}}], "form": {form}}}]"#);
+        // An object that can't be migrated fails the migration, and leaves the file in place.
+        fs::write(&root, legacy(r#"{"Unknown": 1}"#)).unwrap();
+        assert!(Store::open(&root).is_err());
+        assert!(root.is_file());
+        assert!(!with_suffix(&root, ".migrating").exists());
+        fs::write(&root, legacy(r#""PlainText""#)).unwrap();
+        let store = Store::open(&root).unwrap();
+        assert!(root.is_dir() && with_suffix(&root, ".legacy").is_file());
+        assert!(store.handles().unwrap()[&ObjectId::of(b"hi")].tags.contains(&Tag::Title("hi".into())));
+        // Interrupted between moving the file aside and moving the new store into place.
+        fs::rename(&root, with_suffix(&root, ".migrating")).unwrap();
+        let store = Store::open(&root).unwrap();
+        assert!(store.handles().unwrap().contains_key(&ObjectId::of(b"hi")));
+        assert!(!with_suffix(&root, ".migrating").exists());
+    }
+
+    #[test]
+    fn json_catalog_keeps_its_blobs() {
+        let dir = TempDir::new("json-gc");
+        let old = object(b"old");
+        let blob = dir.0.join(BLOBS).join(&old.id.to_string()[..2]).join(old.id.to_string());
+        fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        fs::write(&blob, &old.data).unwrap();
+        fs::write(dir.0.join(JSON_CATALOG), format!(r#"{{"{}": {{"tags": [], "form": "Binary"}}}}"#, old.id)).unwrap();
+        let mut store = Store::open(&dir.0).unwrap();
+        assert!(store.remove(&old.id));
+        store.put(&object(b"new")).unwrap();
+        store.flush().unwrap();
+        assert!(blob.exists());
+        // With the binary catalog damaged the JSON one is read, and its blobs are all there.
+        fs::write(dir.0.join(CATALOG), b"damaged").unwrap();
+        let store = Store::open(&dir.0).unwrap();
+        assert!(store.recovered_from().is_some());
+        assert!(store.handles().unwrap().contains_key(&old.id));
+    }
+
+    #[test]
+    fn damaged_catalog_falls_back_to_a_backup() {
+        let dir = TempDir::new("backup");
+        let mut store = Store::open(&dir.0).unwrap();
+        let (first, second) = (object(b"first"), object(b"second"));
+        store.put(&first).unwrap();
+        store.flush().unwrap();
+        store.put(&second).unwrap();
+        store.flush().unwrap();
+        let mut data = fs::read(dir.0.join(CATALOG)).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(dir.0.join(CATALOG), &data).unwrap();
+        let mut store = Store::open(&dir.0).unwrap();
+        assert!(store.recovered_from() == Some(catalog_path(&dir.0, CATALOG, 1).as_path()));
+        let handles = store.handles().unwrap();
+        assert!(handles.contains_key(&first.id) && !handles.contains_key(&second.id));
+        // The damaged catalog is kept aside rather than becoming a backup.
+        store.flush().unwrap();
+        assert!(with_suffix(&dir.0.join(CATALOG), ".damaged").is_file());
+        assert!(Store::open(&dir.0).unwrap().recovered_from().is_none());
+        // A newer program's catalog is not read as damage.
+        let mut data = fs::read(dir.0.join(CATALOG)).unwrap();
+        data[8] = 0xff;
+        fs::write(dir.0.join(CATALOG), &data).unwrap();
+        assert!(matches!(Store::open(&dir.0), Err(Error::Version {..})));
+    }
+
+    #[test]
+    fn words_are_kept() {
+        let dir = TempDir::new("words");
+        let mut store = Store::open(&dir.0).unwrap();
+        let mut text = object(b"tea for two");
+        store.put(&text).unwrap();
+        assert!(store.words(&text.id).is_none());
+        let words = HashSet::from(["tea".to_string(), "for".into(), "two".into()]);
+        store.set_words(&text.id, &words);
+        text.tags.insert(Tag::Title("Song".into()));
+        store.put(&text).unwrap();
+        store.flush().unwrap();
+        let mut store = Store::open(&dir.0).unwrap();
+        assert!(store.words(&text.id) == Some(words.clone()));
+        store.update(&store.handles().unwrap()[&text.id]);
+        assert!(store.words(&text.id) == Some(words));
+    }
+
+    #[test]
+    fn received_data_is_adopted() {
+        let dir = TempDir::new("receive");
+        let mut store = Store::open(&dir.0).unwrap();
+        let data = vec![7; 200_000];
+        let (id, blob) = receive(&dir.0, &mut data.as_slice()).unwrap();
+        assert!(id == ObjectId::of(data.as_slice()) && blob.size == data.len() as u64);
+        assert!(blob.path.starts_with(dir.0.join(INCOMING)) && fs::read(&blob.path).unwrap() == data);
+        let handle = store.adopt(&Object {id, data: blob.clone(), tags: HashSet::from([Tag::Title("sevens".into())]), form: Form::Binary}).unwrap();
+        assert!(!blob.path.exists() && handle.data.path == store.blob_path(&id));
+        assert!(handle.data.read().unwrap() == data);
+        // The same data again only adds its tags.
+        let (_, again) = receive(&dir.0, &mut data.as_slice()).unwrap();
+        store.adopt(&Object {id, data: again.clone(), tags: HashSet::new(), form: Form::Binary}).unwrap();
+        assert!(!again.path.exists() && handle.data.read().unwrap() == data);
+        store.flush().unwrap();
+        let handles = Store::open(&dir.0).unwrap().handles().unwrap();
+        assert!(handles.len() == 1 && handles[&id].data.size == data.len() as u64);
+    }
+
+    #[test]
+    fn incoming_data_of_running_processes_is_kept() {
+        let dir = TempDir::new("incoming");
+        Store::open(&dir.0).unwrap();
+        let (id, blob) = receive(&dir.0, &mut b"in flight".as_slice()).unwrap();
+        let incoming = dir.0.join(INCOMING);
+        fs::write(incoming.join("4000000000-0"), b"left behind").unwrap();
+        fs::write(incoming.join("4000000001-0"), b"being received").unwrap();
+        let other = File::create(incoming.join("4000000001.lock")).unwrap();
+        other.lock().unwrap();
+        let mut store = Store::open(&dir.0).unwrap();
+        assert!(!incoming.join("4000000000-0").exists());
+        assert!(incoming.join("4000000001-0").exists());
+        store.adopt(&Object {id, data: blob, tags: HashSet::new(), form: Form::Binary}).unwrap();
+        // Once the other process has ended.
+        drop(other);
+        Store::open(&dir.0).unwrap();
+        assert!(!incoming.join("4000000001-0").exists() && !incoming.join("4000000001.lock").exists());
+        assert!(incoming.join(format!("{}.lock", process::id())).exists());
+        receive(&dir.0, &mut b"more".as_slice()).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::store::{Blob, ObjectHandle};
+
+// Downscaled previews of photos, cached as <store>/thumbnails/<size>/<id>.png. An object's id is
+// the hash of its data, so a cached thumbnail never goes stale: changed data gets a new id, and
+// with it a new thumbnail.
+const THUMBNAILS: &str = "thumbnails";
+
+// The sizes thumbnails are made in, as the length of their longer side in pixels.
+pub const SIZES: [u32; 3] = [64, 128, 256];
+
+// The smallest thumbnail size that is at least `size`, or the largest there is.
+pub fn size_for(size: u32) -> u32 {
+    SIZES.into_iter().find(|s| *s >= size).unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+fn path(store: &Path, id: &ObjectId, size: u32) -> PathBuf {
+    store.join(THUMBNAILS).join(size.to_string()).join(format!("{id}.png"))
+}
+
+// A PNG thumbnail of a photo, from the cache or made and cached now.
+pub fn thumbnail(store: &Path, object: &ObjectHandle, size: u32) -> Result<Vec<u8>> {
+    let path = path(store, &object.id, size);
+    if let Ok(png) = fs::read(&path) {
+        return Ok(png);
+    }
+    let image = decode(&object.data)?;
+    let (width, height) = image.dimensions();
+    let image = if width > size || height > size { image.thumbnail(size, size) } else { image };
+    let mut png = vec![];
+    image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).map_err(|e| Error::Decode(e.to_string()))?;
+    // Written aside and renamed so an interrupted write is never taken for a thumbnail, under a
+    // name of its own as another worker may be making the same thumbnail. The cache is only there
+    // for speed, so failing to write it isn't an error.
+    static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+    let temporary = path.with_extension(format!("png.{}-{}.tmp", process::id(), WRITTEN.fetch_add(1, Ordering::Relaxed)));
+    if fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&temporary, &png)).and_then(|_| fs::rename(&temporary, &path)).is_err() {
+        let _ = fs::remove_file(temporary);
+    }
+    Ok(png)
+}
+
+// Decodes a photo straight from the store, without reading all of its data first.
+pub fn decode(blob: &Blob) -> Result<DynamicImage> {
+    image::io::Reader::new(BufReader::new(blob.open()?)).with_guessed_format()
+        .map_err(|e| Error::Decode(e.to_string()))?
+        .decode().map_err(|e| Error::Decode(e.to_string()))
+}
+
+pub fn remove(store: &Path, id: &ObjectId) {
+    for size in SIZES {
+        let _ = fs::remove_file(path(store, id, size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::thread;
+    use image::RgbImage;
+    use crate::object::{Form, Object};
+    use crate::store::Store;
+    use crate::store::tests::TempDir;
+    use super::*;
+
+    #[test]
+    fn thumbnails_made_at_once_dont_collide() {
+        let dir = TempDir::new("thumbnails");
+        let mut png = vec![];
+        DynamicImage::ImageRgb8(RgbImage::from_fn(300, 200, |x, y| image::Rgb([x as u8, y as u8, 0])))
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).unwrap();
+        let photo = Store::open(&dir.0).unwrap().put(&Object {id: ObjectId::of(png.as_slice()), data: png, tags: HashSet::new(), form: Form::Photo}).unwrap();
+        let made = thread::scope(|scope| {
+            let workers = (0..8).map(|_| scope.spawn(|| thumbnail(&dir.0, &photo, 64).unwrap())).collect::<Vec<_>>();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert!(made.iter().all(|png| *png == made[0]));
+        assert!(image::load_from_memory(made[0].as_slice()).unwrap().dimensions() == (64, 43));
+        let cached = fs::read_dir(path(&dir.0, &photo.id, 64).parent().unwrap()).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+        assert!(cached == [path(&dir.0, &photo.id, 64)]);
+    }
+}
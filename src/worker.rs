@@ -0,0 +1,166 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use image::RgbaImage;
+use crate::error::{panic_message, Error, Result};
+use crate::import::{receive_file, scan, Received, SymlinkPolicy};
+use crate::magic_identify::Registry;
+use crate::object::ObjectId;
+use crate::similar::perceptual_hash;
+use crate::store::ObjectHandle;
+use crate::thumbnail::{self, thumbnail};
+
+// The work that is too slow to do while drawing a frame.
+pub enum Job {
+    Import {path: PathBuf, symlinks: SymlinkPolicy}, // A file, or every file under a directory.
+    Thumbnail {object: ObjectHandle, size: u32},
+    Decode {object: ObjectHandle}, // A photo at full size.
+    Hash {object: ObjectHandle}, // The perceptual hash of a photo, see similar.rs.
+}
+
+// What the workers report back. Each file that is imported is first Queued, then Started, and
+// ends up Imported, Failed or Cancelled.
+pub enum Event {
+    Queued(PathBuf),
+    Started(PathBuf),
+    Imported(PathBuf, Box<Received>), // Received into the store and identified, but not yet in the library.
+    Failed(PathBuf, Error),
+    Cancelled(PathBuf),
+    Thumbnail(ObjectId, u32, Result<RgbaImage>),
+    Decoded(ObjectId, Result<RgbaImage>),
+    Hashed(ObjectId, Result<u64>),
+}
+
+// Imports are tagged with the generation they were submitted in. Cancelling starts a new
+// generation, and work left over from older ones is skipped.
+enum Task {
+    Job(Job, usize),
+    File {path: PathBuf, categories: Vec<String>, generation: usize}, // Found while importing a directory.
+}
+
+struct Shared {
+    store: PathBuf,
+    identifiers: Arc<Registry>,
+    generation: AtomicUsize,
+    tasks: Sender<Task>,
+    events: Sender<Event>,
+    notify: Box<dyn Fn() + Send + Sync>,
+}
+
+// A pool of threads that works through jobs in the order they were submitted. Results are
+// collected with `events`; `notify` is called whenever there is a new one, e.g. to wake up the UI.
+pub struct Workers {
+    shared: Arc<Shared>,
+    events: Receiver<Event>,
+}
+
+impl Workers {
+    pub fn new(threads: usize, store: &Path, identifiers: Arc<Registry>, notify: impl Fn() + Send + Sync + 'static) -> Workers {
+        let (tasks, queue) = channel();
+        let (events, received) = channel();
+        let shared = Arc::new(Shared {
+            store: store.to_path_buf(),
+            identifiers,
+            generation: AtomicUsize::new(0),
+            tasks,
+            events,
+            notify: Box::new(notify),
+        });
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..threads.max(1) {
+            let (shared, queue) = (shared.clone(), queue.clone());
+            thread::spawn(move || work(&shared, &queue));
+        }
+        Workers {shared, events: received}
+    }
+
+    pub fn submit(&self, job: Job) {
+        let generation = self.shared.generation.load(Ordering::SeqCst);
+        self.shared.tasks.send(Task::Job(job, generation)).ok();
+    }
+
+    // Skips every import that hasn't started yet. Files that are being read are still reported.
+    pub fn cancel_imports(&self) {
+        self.shared.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // The events that came in since the last call, without waiting for more.
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        self.events.try_iter()
+    }
+}
+
+fn work(shared: &Shared, queue: &Mutex<Receiver<Task>>) {
+    loop {
+        let task = match queue.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => return,
+        };
+        let current = shared.generation.load(Ordering::SeqCst);
+        let events = match task {
+            Task::Job(Job::Import {path, ..}, generation) | Task::File {path, generation, ..} if generation != current => vec![Event::Cancelled(path)],
+            Task::Job(Job::Import {path, symlinks}, generation) => {
+                // Queued is sent before the file is, so it always arrives before Started.
+                for (path, categories) in scan(&path, symlinks) {
+                    match categories {
+                        Ok(categories) => {
+                            shared.events.send(Event::Queued(path.clone())).ok();
+                            shared.tasks.send(Task::File {path, categories, generation}).ok();
+                        }
+                        Err(e) => {
+                            shared.events.send(Event::Failed(path, e)).ok();
+                        }
+                    }
+                }
+                vec![]
+            }
+            Task::File {path, categories, ..} => {
+                shared.events.send(Event::Started(path.clone())).ok();
+                match guarded(|| receive_file(&path, categories.as_slice(), &shared.identifiers, &shared.store)) {
+                    Ok(object) => vec![Event::Imported(path, Box::new(object))],
+                    Err(e) => vec![Event::Failed(path, e)],
+                }
+            }
+            Task::Job(Job::Thumbnail {object, size}, _) => {
+                let image = guarded(|| thumbnail(&shared.store, &object, size)
+                    .and_then(|png| image::load_from_memory(png.as_slice()).map_err(|e| Error::Decode(e.to_string())))
+                    .map(|image| image.to_rgba8()));
+                vec![Event::Thumbnail(object.id, size, image)]
+            }
+            Task::Job(Job::Decode {object}, _) => {
+                vec![Event::Decoded(object.id, guarded(|| thumbnail::decode(&object.data).map(|image| image.to_rgba8())))]
+            }
+            Task::Job(Job::Hash {object}, _) => {
+                vec![Event::Hashed(object.id, guarded(|| perceptual_hash(&shared.store, &object)))]
+            }
+        };
+        for event in events {
+            if shared.events.send(event).is_err() {
+                return;
+            }
+        }
+        (shared.notify)();
+    }
+}
+
+// Runs a job, turning a panic into an error, so that a decoder choking on hostile data fails
+// that job alone rather than the thread and everything queued behind it.
+fn guarded<T>(job: impl FnOnce() -> Result<T>) -> Result<T> {
+    catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|panic| Err(Error::Panic(panic_message(panic.as_ref()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        assert!(guarded(|| Ok(1)).is_ok_and(|n| n == 1));
+        assert!(matches!(guarded::<()>(|| panic!("boom")), Err(Error::Panic(message)) if message == "boom"));
+        let n = 3;
+        assert!(matches!(guarded::<()>(|| panic!("boom {n}")), Err(Error::Panic(message)) if message == "boom 3"));
+    }
+}